@@ -0,0 +1,41 @@
+//! How fast the reinforcement-learning environment steps: a batch at 80x24
+//! with random actions, the way a training run with an untrained policy
+//! would, printing how many steps and episodes per second that makes.
+//!
+//! ```text
+//! cargo run --release --example bench_env
+//! ```
+
+use nibbles::Direction;
+use nibbles::env::{Env, Rewards, VecEnv};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Instant;
+
+const ENVS: usize = 64;
+const STEPS: usize = 5000;
+
+fn main() {
+    let rewards = Rewards { approach: 0.1, ..Rewards::default() };
+    let mut envs = VecEnv::new((0..ENVS).map(|_| Env::new((80, 24)).rewards(rewards).end_on_death(true)).collect());
+    let (channels, rows, columns) = envs.shape();
+    let mut rng = StdRng::seed_from_u64(0x656e76);
+    let mut actions = vec![Direction::Right; envs.len()];
+
+    let start = Instant::now();
+    envs.reset(rng.gen());
+    let mut episodes = 0;
+    for _ in 0..STEPS {
+        for action in actions.iter_mut() {
+            if rng.gen_ratio(1, 8) {
+                *action = rng.gen();
+            }
+        }
+        let (_, _, dones) = envs.step(&actions);
+        episodes += dones.iter().filter(|&&done| done).count();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    println!("{} environments, {channels}x{rows}x{columns} observations", envs.len());
+    println!("{:.0} steps/s, {:.0} episodes/s", (ENVS * STEPS) as f64 / elapsed, episodes as f64 / elapsed);
+}
@@ -0,0 +1,49 @@
+//! A bot for the line-based JSON protocol `nibbles --bot` speaks: it heads
+//! for the food on the shortest way that doesn't run into anything right
+//! away, and otherwise takes whichever way is open. Now and then it wanders
+//! off at random, which gets it out of going round in circles behind a wall.
+//!
+//! ```text
+//! cargo build --release --example bot
+//! cargo run --release -- --bot target/release/examples/bot --bot-games 10
+//! ```
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+
+const MOVES: [(&str, i64, i64); 4] = [("up", 0, -1), ("right", 1, 0), ("down", 0, 1), ("left", -1, 0)];
+
+fn main() -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let state: Value = serde_json::from_str(&line?)?;
+        let direction = choose(&state);
+        writeln!(out, "{}", serde_json::json!({ "direction": direction }))?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+fn choose(state: &Value) -> Option<&'static str> {
+    let cell = |value: &Value| (value[0].as_i64().unwrap_or(0), value[1].as_i64().unwrap_or(0));
+    let number = |key: &str| state[key].as_i64().unwrap_or(0);
+    let (width, height) = (number("width"), number("height"));
+
+    let snake: Vec<(i64, i64)> = state["snake"].as_array()?.iter().map(cell).collect();
+    let mut blocked: HashSet<(i64, i64)> = state["walls"].as_array()?.iter().map(cell).collect();
+    // the tail moves out of the way
+    blocked.extend(snake.iter().take(snake.len().saturating_sub(1)));
+    let free = |(x, y): (i64, i64)| x >= 1 && y >= 1 && x < width && y <= height && !blocked.contains(&(x, y));
+
+    let head = *snake.first()?;
+    let food = (state["food"]["x"].as_i64()?, state["food"]["y"].as_i64()?);
+    let distance = |(x, y): (i64, i64)| (x - food.0).abs() + (y - food.1).abs().min((y - food.1 - 1).abs());
+
+    let wander = rand::random::<u8>() < 32;
+    MOVES.iter()
+        .map(|&(name, dx, dy)| (name, (head.0 + dx, head.1 + dy)))
+        .filter(|&(_, next)| free(next))
+        .min_by_key(|&(_, next)| if wander { rand::random() } else { distance(next) })
+        .map(|(name, _)| name)
+}
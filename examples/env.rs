@@ -0,0 +1,85 @@
+//! Plays a few episodes of the reinforcement-learning environment with a
+//! greedy policy that reads the observation: it heads for the number along
+//! whichever axis is further off, and turns aside when the next pixel is a
+//! wall or the snake.
+//!
+//! ```text
+//! cargo run --example env
+//! ```
+
+use nibbles::Direction;
+use nibbles::env::{CHANNELS, Env, FOOD, SNAKE, TILES};
+
+const EPISODES: u64 = 3;
+const MAX_STEPS: usize = 10_000;
+
+fn main() {
+    let mut env = Env::new((80, 24)).end_on_death(true);
+    let (channels, rows, columns) = env.shape();
+    assert_eq!(channels, CHANNELS);
+
+    for seed in 0..EPISODES {
+        let mut observation = env.reset(seed).to_vec();
+        let mut heading = Direction::Right;
+        let (mut total, mut steps) = (0.0, 0);
+        while steps < MAX_STEPS {
+            heading = policy(&observation, rows, columns, heading);
+            let (next, reward, done) = env.step(heading);
+            observation = next.to_vec();
+            total += reward;
+            steps += 1;
+            if done {
+                break;
+            }
+        }
+        println!("episode {seed}: {steps} steps, return {total}");
+    }
+}
+
+fn policy(observation: &[u8], rows: usize, columns: usize, heading: Direction) -> Direction {
+    let plane = |channel: usize| &observation[channel * rows * columns..(channel + 1) * rows * columns];
+    let position = |channel: usize, matches: fn(u8) -> bool| {
+        plane(channel).iter().position(|&value| matches(value)).map(|index| ((index % columns) as i32, (index / columns) as i32))
+    };
+    let Some(head) = position(SNAKE, |value| value == 2) else { return heading };
+    let food = position(FOOD, |value| value != 0).unwrap_or(head);
+
+    let free = |direction: Direction| {
+        let (x, y) = step(head, direction);
+        (0..columns as i32).contains(&x) && (0..rows as i32).contains(&y) && {
+            let index = y as usize * columns + x as usize;
+            plane(TILES)[index] == 0 && plane(SNAKE)[index] == 0
+        }
+    };
+    let (dx, dy) = (food.0 - head.0, food.1 - head.1);
+    let towards = if dx.abs() > dy.abs() {
+        if dx > 0 { Direction::Right } else { Direction::Left }
+    } else if dy > 0 {
+        Direction::Down
+    } else {
+        Direction::Up
+    };
+    // the environment ignores turning back, so that's never worth trying
+    [towards, heading, Direction::Up, Direction::Right, Direction::Down, Direction::Left]
+        .into_iter()
+        .find(|&direction| direction != opposite(heading) && free(direction))
+        .unwrap_or(heading)
+}
+
+fn step((x, y): (i32, i32), direction: Direction) -> (i32, i32) {
+    match direction {
+        Direction::Up => (x, y - 1),
+        Direction::Right => (x + 1, y),
+        Direction::Down => (x, y + 1),
+        Direction::Left => (x - 1, y)
+    }
+}
+
+fn opposite(direction: Direction) -> Direction {
+    match direction {
+        Direction::Up => Direction::Down,
+        Direction::Right => Direction::Left,
+        Direction::Down => Direction::Up,
+        Direction::Left => Direction::Right
+    }
+}
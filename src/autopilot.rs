@@ -0,0 +1,268 @@
+use std::collections::VecDeque;
+
+use crate::snake::Snake;
+use crate::{Board, Direction, GameEvent, GameState, SnakeCell};
+
+const MOVES: [(GameEvent, Direction, i32, i32); 4] = [
+    (GameEvent::Up, Direction::Up, 0, -1),
+    (GameEvent::Right, Direction::Right, 1, 0),
+    (GameEvent::Down, Direction::Down, 0, 1),
+    (GameEvent::Left, Direction::Left, -1, 0)
+];
+
+/// How many moves a light-cycle trail is taken to stay, longer than any
+/// path on a board.
+const TRAIL: u32 = u16::MAX as u32;
+
+/// Picks the direction for the player's snake's next tick, see `route`.
+/// Returns `None` when going straight on is as good as anything.
+pub(crate) fn steer(game_state: &GameState, board: &Board) -> Option<GameEvent> {
+    let others: Vec<&Snake> = game_state.opponents.iter().map(|opponent| &opponent.snake).collect();
+    let direction = route(game_state, board, &game_state.snake, game_state.snake_direction, game_state.extending, &others)?;
+    MOVES.iter().find(|&&(_, d, ..)| d == direction).map(|&(event, ..)| event)
+}
+
+/// Picks the direction for `snake`'s next tick: the shortest way to the
+/// number, as long as the snake can still reach its own tail once it got
+/// there. Failing that it chases its tail, and failing that it heads for the
+/// most room. The `others` snakes are in the way until their tails moved on.
+/// Returns `None` when going straight on is as good as anything.
+pub(crate) fn route(game_state: &GameState, board: &Board, snake: &Snake, heading: Direction, extending: u8, others: &[&Snake]) -> Option<Direction> {
+    if snake.is_empty() {
+        return None;
+    }
+    let snake: Vec<SnakeCell> = snake.iter().copied().collect();
+    let others: Vec<Vec<SnakeCell>> = others.iter().map(|other| other.iter().copied().collect()).collect();
+    // trails never move on in light-cycle games
+    let (extending, others_extending) = if game_state.light_cycle { (TRAIL, TRAIL) } else { (extending as u32, 0) };
+    let mut grid = Grid::new(board, &snake, extending);
+    // whether the others grow meanwhile isn't known, so short of trails
+    // they're assumed not to
+    for other in others.iter() {
+        grid.occupy(other, others_extending, 0);
+    }
+
+    // with neither a number nor a tail that gets out of the way, all that's
+    // left is to keep to the most room
+    if !game_state.light_cycle {
+        let (x, y) = game_state.number_pos;
+        let food = [(x as i32, (y - y % 2) as i32), (x as i32, (y - y % 2) as i32 + 1)];
+        if let Some(path) = grid.path(snake[0], heading, |cell, time| {
+            food.contains(&(cell.0, cell.1)) && grid.free_at(cell, time)
+        }) {
+            let eaten = game_state.current_number as u32 * 4;
+            if survives(board, &snake, &path, extending, eaten, &others) {
+                return turn(heading, snake[0], path[0]);
+            }
+        }
+
+        let tail = snake[snake.len() - 1];
+        if snake.len() > 2 {
+            if let Some(path) = grid.path(snake[0], heading, |cell, _| cell == tail) {
+                if grid.room(path[0]) > extending as usize {
+                    return turn(heading, snake[0], path[0]);
+                }
+            }
+        }
+    }
+
+    let roomiest = MOVES.iter()
+        .filter(|(_, direction, ..)| allowed(heading, *direction))
+        .map(|&(_, _, dx, dy)| SnakeCell(snake[0].0 + dx, snake[0].1 + dy))
+        .filter(|&cell| grid.free_at(cell, 1))
+        .max_by_key(|&cell| grid.room(cell))?;
+    turn(heading, snake[0], roomiest)
+}
+
+/// Whether the snake could still get to its tail after following `path` to
+/// the number, growing on the way while `extending` and by `eaten` cells once
+/// it got there. It can idle in whatever room is left around the head while it
+/// waits for its body to clear the way.
+fn survives(board: &Board, snake: &[SnakeCell], path: &[SnakeCell], extending: u32, eaten: u32, others: &[Vec<SnakeCell>]) -> bool {
+    let length = snake.len() + extending.min(path.len() as u32) as usize;
+    let body: Vec<SnakeCell> = path.iter().rev().chain(snake.iter()).take(length).copied().collect();
+    let mut grid = Grid::new(board, &body, eaten);
+    for other in others {
+        grid.occupy(other, 0, path.len() as u32);
+    }
+    let tail = body[body.len() - 1];
+    let heading = direction_between(path.get(path.len().wrapping_sub(2)).copied().unwrap_or(snake[0]), body[0]);
+
+    let room = MOVES.iter()
+        .map(|&(_, _, dx, dy)| grid.room(SnakeCell(body[0].0 + dx, body[0].1 + dy)))
+        .max()
+        .unwrap_or(0);
+    // the tail won't move on while the snake grows
+    if room <= eaten as usize {
+        return false;
+    }
+    grid.idle(room as u32);
+    grid.path(body[0], heading, |cell, _| cell == tail).is_some()
+}
+
+fn direction_between(from: SnakeCell, to: SnakeCell) -> Direction {
+    MOVES.iter()
+        .find(|&&(_, _, dx, dy)| SnakeCell(from.0 + dx, from.1 + dy) == to)
+        .map_or(Direction::Right, |&(_, direction, ..)| direction)
+}
+
+/// Whether the snake can go `direction` next, which is anything but back.
+fn allowed(heading: Direction, direction: Direction) -> bool {
+    direction == heading || Direction::get_valid_transition(heading, direction).is_some()
+}
+
+/// The direction that turns the snake from `head` towards `next`, or `None`
+/// if it is already heading there.
+fn turn(heading: Direction, head: SnakeCell, next: SnakeCell) -> Option<Direction> {
+    let direction = direction_between(head, next);
+    (direction != heading).then_some(direction)
+}
+
+/// For every pixel, how many moves it stays blocked: forever for walls, until
+/// the part of the snake on it has moved on for the body, not at all for
+/// everything else. A pixel blocked for `n` moves can be entered on move
+/// `n + 1`.
+struct Grid {
+    width: usize,
+    height: usize,
+    free: Vec<u32>
+}
+
+impl Grid {
+    fn new(board: &Board, snake: &[SnakeCell], extending: u32) -> Grid {
+        let (width, height) = (board.width, board.height);
+        let mut free = vec![0; width * (height + 1)];
+        for y in 0..=height {
+            for x in 0..width {
+                // the same bounds `update` checks the head against
+                if x < 1 || y < 1 || board.lookup(x, y) >= 0 {
+                    free[y * width + x] = u32::MAX;
+                }
+            }
+        }
+        let mut grid = Grid { width, height, free };
+        grid.occupy(snake, extending, 0);
+        grid
+    }
+
+    /// Marks the pixels under `snake` as blocked until its tail moved past
+    /// them, which takes `extending` moves longer while it grows, counting
+    /// from `elapsed` moves from now.
+    fn occupy(&mut self, snake: &[SnakeCell], extending: u32, elapsed: u32) {
+        let length = snake.len() as u32;
+        for (index, &SnakeCell(x, y)) in snake.iter().enumerate() {
+            if let Some(slot) = Self::index(self.width, self.height, x, y) {
+                let leaves = (length - 1 - index as u32 + extending).saturating_sub(elapsed);
+                self.free[slot] = self.free[slot].max(leaves);
+            }
+        }
+    }
+
+    /// Pretends the snake spent `moves` moves going round in circles, so the
+    /// body has moved on that much further before the path starts.
+    fn idle(&mut self, moves: u32) {
+        for free in self.free.iter_mut().filter(|free| **free != u32::MAX) {
+            *free = free.saturating_sub(moves);
+        }
+    }
+
+    fn index(width: usize, height: usize, x: i32, y: i32) -> Option<usize> {
+        (x >= 0 && y >= 0 && (x as usize) < width && (y as usize) <= height).then(|| y as usize * width + x as usize)
+    }
+
+    /// Whether `cell` can be entered on move `time`, counting from 1.
+    fn free_at(&self, SnakeCell(x, y): SnakeCell, time: u32) -> bool {
+        Self::index(self.width, self.height, x, y).is_some_and(|slot| self.free[slot] < time)
+    }
+
+    /// The shortest path from `head` to a pixel for which `goal` holds when
+    /// getting there on the given move, without turning back on the first
+    /// move. Doesn't include `head`.
+    fn path(&self, head: SnakeCell, heading: Direction, goal: impl Fn(SnakeCell, u32) -> bool) -> Option<Vec<SnakeCell>> {
+        let mut from = vec![usize::MAX; self.free.len()];
+        let mut queue = VecDeque::from([(head, 0)]);
+        let start = Self::index(self.width, self.height, head.0, head.1)?;
+        from[start] = start;
+
+        while let Some((cell, time)) = queue.pop_front() {
+            for &(_, direction, dx, dy) in MOVES.iter() {
+                if time == 0 && !allowed(heading, direction) {
+                    continue;
+                }
+                let next = SnakeCell(cell.0 + dx, cell.1 + dy);
+                let Some(slot) = Self::index(self.width, self.height, next.0, next.1) else { continue };
+                if from[slot] != usize::MAX {
+                    continue;
+                }
+                let reached = goal(next, time + 1);
+                // blocked pixels aren't marked as visited, since they may have
+                // freed up by the time a longer way gets there
+                if !reached && !self.free_at(next, time + 1) {
+                    continue;
+                }
+                from[slot] = Self::index(self.width, self.height, cell.0, cell.1).unwrap();
+                if reached {
+                    return Some(self.trace(&from, start, slot));
+                }
+                queue.push_back((next, time + 1));
+            }
+        }
+        None
+    }
+
+    fn trace(&self, from: &[usize], start: usize, mut slot: usize) -> Vec<SnakeCell> {
+        let mut path = Vec::new();
+        while slot != start {
+            path.push(SnakeCell((slot % self.width) as i32, (slot / self.width) as i32));
+            slot = from[slot];
+        }
+        path.reverse();
+        path
+    }
+
+    /// How many pixels can be reached from `cell`, ignoring that the snake
+    /// moves out of the way meanwhile.
+    fn room(&self, cell: SnakeCell) -> usize {
+        let mut seen = vec![false; self.free.len()];
+        let mut stack = vec![cell];
+        let mut count = 0;
+        while let Some(SnakeCell(x, y)) = stack.pop() {
+            let Some(slot) = Self::index(self.width, self.height, x, y) else { continue };
+            if seen[slot] || self.free[slot] > 0 {
+                continue;
+            }
+            seen[slot] = true;
+            count += 1;
+            stack.extend(MOVES.iter().map(|&(_, _, dx, dy)| SnakeCell(x + dx, y + dy)));
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{advance, create_game_state, shown_board};
+
+    #[test]
+    fn clears_the_first_levels_without_a_mistake() {
+        for seed in 0..4 {
+            let mut game_state = create_game_state(seed, (80, 24));
+            for _ in 0..5000 {
+                if game_state.level == 3 {
+                    break;
+                }
+                if game_state.paused {
+                    advance(GameEvent::Action, &mut game_state);
+                }
+                let board = shown_board(&mut game_state);
+                if let Some(turn) = steer(&game_state, &board) {
+                    advance(turn, &mut game_state);
+                }
+                advance(GameEvent::Timeout, &mut game_state);
+                assert_eq!(game_state.lives, 5, "seed {seed}, level {}", game_state.level);
+            }
+            assert_eq!(game_state.level, 3, "seed {seed}");
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use crossterm::terminal::{BeginSynchronizedUpdate, Clear, ClearType, EndSynchronizedUpdate};
+use crossterm::{cursor, queue, style};
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::terminal::{Terminal, TermColor, Cell};
+
+/// What it took to show a frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FrameStats {
+    /// Bytes sent to the terminal, escape sequences included.
+    pub bytes: usize,
+    /// Number of runs of adjacent changed cells that were written.
+    pub runs: usize,
+    /// Whether the frame was dropped because the output is backed up.
+    pub skipped: bool
+}
+
+/// Something that can show the frames produced by the `Renderer`.
+pub(crate) trait Backend {
+    /// Shows `frame`, replacing whatever was shown before.
+    fn draw(&mut self, frame: &Terminal) -> io::Result<FrameStats>;
+
+    /// Forgets the previous frame, so the next `draw` repaints everything.
+    fn invalidate(&mut self) -> io::Result<()>;
+}
+
+/// Writes frames as crossterm escape sequences, only sending the cells that
+/// changed since the previous frame.
+pub(crate) struct CrosstermBackend<W: Write> {
+    out: W,
+    state: TermState,
+    synchronized: bool,
+    bandwidth: Option<Bandwidth>,
+    throughput: Throughput
+}
+
+/// Limits what a `CrosstermBackend` sends, for playing over slow links.
+struct Bandwidth {
+    frame_budget: usize,
+    frame_interval: Duration,
+    skip_until: Option<Instant>
+}
+
+/// Totals over every frame a backend was asked to draw.
+#[derive(Default)]
+pub(crate) struct Throughput {
+    started: Option<Instant>,
+    bytes: usize,
+    frames: usize,
+    skipped: usize
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.started.map(|started| started.elapsed().as_secs_f64()).unwrap_or_default();
+        let rate = if seconds > 0.0 { self.bytes as f64 / seconds } else { 0.0 };
+        write!(
+            f, "sent {:.1} KiB in {} frames ({} skipped), {:.2} KiB/s",
+            self.bytes as f64 / 1024.0, self.frames, self.skipped, rate / 1024.0
+        )
+    }
+}
+
+impl<W: Write> CrosstermBackend<W> {
+    pub(crate) fn new(out: W) -> CrosstermBackend<W> {
+        CrosstermBackend {
+            out,
+            state: TermState::default(),
+            synchronized: true,
+            bandwidth: None,
+            throughput: Throughput::default()
+        }
+    }
+
+    /// Caps every frame at what `bytes_per_second` allows for one
+    /// `frame_interval`. Changes that don't fit are sent with the following
+    /// frames, and frames are skipped entirely while writing blocks for
+    /// longer than a frame interval.
+    pub(crate) fn bandwidth(mut self, bytes_per_second: usize, frame_interval: Duration) -> CrosstermBackend<W> {
+        let frame_budget = (bytes_per_second as f64 * frame_interval.as_secs_f64()) as usize;
+        self.bandwidth = Some(Bandwidth { frame_budget, frame_interval, skip_until: None });
+        self
+    }
+
+    pub(crate) fn throughput(&self) -> &Throughput {
+        &self.throughput
+    }
+
+    /// Whether to wrap every frame in the synchronized output mode (`?2026`),
+    /// so terminals that support it show the frame at once. Terminals that
+    /// don't know the mode should ignore it, but some print it.
+    pub(crate) fn synchronized(mut self, synchronized: bool) -> CrosstermBackend<W> {
+        self.synchronized = synchronized;
+        self
+    }
+}
+
+impl<W: Write> Backend for CrosstermBackend<W> {
+    fn draw(&mut self, frame: &Terminal) -> io::Result<FrameStats> {
+        let start = Instant::now();
+        self.throughput.started.get_or_insert(start);
+        self.throughput.frames += 1;
+        if let Some(Bandwidth { skip_until: Some(until), .. }) = self.bandwidth {
+            if start < until {
+                self.throughput.skipped += 1;
+                return Ok(FrameStats { skipped: true, ..FrameStats::default() });
+            }
+        }
+
+        let framing = if self.synchronized { SYNC_FRAMING_LEN } else { 0 };
+        let limit = self.bandwidth.as_ref().map(|bandwidth| bandwidth.frame_budget.saturating_sub(framing));
+        let mut bytes = Vec::new();
+        let runs = draw_terminal(frame, &mut self.state, &mut bytes, limit)?;
+        if bytes.is_empty() {
+            return Ok(FrameStats::default());
+        }
+        if self.synchronized {
+            queue!(self.out, BeginSynchronizedUpdate)?;
+            self.out.write_all(&bytes)?;
+            queue!(self.out, EndSynchronizedUpdate)?;
+        } else {
+            self.out.write_all(&bytes)?;
+        }
+        self.out.flush()?;
+
+        if let Some(bandwidth) = &mut self.bandwidth {
+            // writes only block for that long when the link is backed up
+            let elapsed = start.elapsed();
+            bandwidth.skip_until = (elapsed > bandwidth.frame_interval)
+                .then(|| Instant::now() + (elapsed - bandwidth.frame_interval));
+        }
+        let stats = FrameStats { bytes: bytes.len() + framing, runs, skipped: false };
+        self.throughput.bytes += stats.bytes;
+        Ok(stats)
+    }
+
+    fn invalidate(&mut self) -> io::Result<()> {
+        // the clear is sent with the next frame, so it ends up inside the
+        // synchronized update and doesn't flash
+        self.state = TermState { clear: true, ..TermState::default() };
+        Ok(())
+    }
+}
+
+/// Length of `ESC [ ? 2026 h` plus `ESC [ ? 2026 l`.
+const SYNC_FRAMING_LEN: usize = 16;
+
+/// Keeps the escape sequences in memory instead of sending them anywhere.
+pub(crate) type RecordingBackend = CrosstermBackend<Vec<u8>>;
+
+impl RecordingBackend {
+    pub(crate) fn recording() -> RecordingBackend {
+        CrosstermBackend::new(Vec::new())
+    }
+
+    /// Returns everything written since the last call.
+    pub(crate) fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+}
+
+/// Holds on to the last frame without producing any output.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MemoryBackend {
+    pub frame: Option<Terminal>,
+    pub frames_drawn: usize
+}
+
+#[cfg(test)]
+impl Backend for MemoryBackend {
+    fn draw(&mut self, frame: &Terminal) -> io::Result<FrameStats> {
+        match &mut self.frame {
+            Some(last) => last.clone_from(frame),
+            None => self.frame = Some(frame.clone())
+        }
+        self.frames_drawn += 1;
+        Ok(FrameStats::default())
+    }
+
+    fn invalidate(&mut self) -> io::Result<()> {
+        self.frame = None;
+        Ok(())
+    }
+}
+
+fn cell_diff(a: Cell, b: Cell) -> bool {
+    if a.data != b.data {
+        return true;
+    }
+    if a.foreground != b.foreground {
+        return true;
+    }
+    if a.background != b.background {
+        return true;
+    }
+    false
+}
+
+struct TermState {
+    cursor: (u16, u16),
+    buffer: Option<Terminal>,
+    foreground: TermColor,
+    background: TermColor,
+    clear: bool
+}
+
+impl Default for TermState {
+    fn default() -> Self {
+        Self {
+            cursor: (0, 0),
+            buffer: None,
+            foreground: TermColor::Default,
+            background: TermColor::Default,
+            clear: false
+        }
+    }
+}
+
+fn to_style_color(color: TermColor) -> style::Color {
+    match color {
+        TermColor::Default => style::Color::Reset,
+        TermColor::Color(c) => style::Color::AnsiValue(c)
+    }
+}
+
+/// Writes the difference between `buffer` and what is on screen to `out` and
+/// returns the number of runs written. Adjacent changed cells with the same
+/// colours are written as a single run, so cursor movement and colour changes
+/// are only emitted at run boundaries.
+///
+/// With a `limit`, runs that would take `out` past it are left out. They stay
+/// different from what `term_state` remembers as being on screen, so they go
+/// out with one of the next frames.
+fn draw_terminal(buffer: &Terminal, term_state: &mut TermState, out: &mut Vec<u8>, limit: Option<usize>) -> io::Result<usize> {
+    let (width, height) = buffer.size;
+    let mut screen = match term_state.buffer.take().filter(|p| p.size == buffer.size) {
+        Some(screen) if !term_state.clear => screen,
+        _ => {
+            // we don't know what's on screen, so start from a blank one
+            term_state.clear = true;
+            Terminal::new(width, height)
+        }
+    };
+    if std::mem::take(&mut term_state.clear) {
+        queue!(out, style::ResetColor, Clear(ClearType::All))?;
+        term_state.cursor = (u16::MAX, u16::MAX);
+        term_state.foreground = TermColor::Default;
+        term_state.background = TermColor::Default;
+    }
+
+    let mut runs = 0;
+    let mut text = String::new();
+    let mut run = Vec::new();
+    'rows: for (y, row) in buffer.rows.iter().enumerate() {
+        let mut x = 0;
+        while x < row.len() {
+            let first = row[x];
+            if !cell_diff(first, screen.rows[y][x]) {
+                x += 1;
+                continue;
+            }
+
+            let start = x;
+            text.clear();
+            while x < row.len() && cell_diff(row[x], screen.rows[y][x])
+                && row[x].foreground == first.foreground && row[x].background == first.background {
+                text.push(row[x].data);
+                x += 1;
+            }
+
+            run.clear();
+            let (c_x, c_y) = term_state.cursor;
+            let (r_x, r_y) = (start as u16, y as u16);
+            if c_y == r_y && r_x > c_x {
+                queue!(run, cursor::MoveRight(r_x - c_x))?;
+            } else if (c_x, c_y) != (r_x, r_y) {
+                queue!(run, cursor::MoveTo(r_x, r_y))?;
+            }
+
+            let recolor = first.foreground != term_state.foreground || first.background != term_state.background;
+            if recolor {
+                if first.foreground == TermColor::Default && first.background == TermColor::Default {
+                    queue!(run, style::ResetColor)?;
+                } else {
+                    let colors = style::Colors {
+                        foreground: (first.foreground != term_state.foreground).then(|| to_style_color(first.foreground)),
+                        background: (first.background != term_state.background).then(|| to_style_color(first.background))
+                    };
+                    queue!(run, style::SetColors(colors))?;
+                }
+            }
+            run.extend_from_slice(text.as_bytes());
+
+            // always let one run through, so a tiny limit can't stall drawing
+            if limit.is_some_and(|limit| runs > 0 && out.len() + run.len() > limit) {
+                break 'rows;
+            }
+            out.extend_from_slice(&run);
+            screen.rows[y][start..x].copy_from_slice(&row[start..x]);
+            if recolor {
+                term_state.foreground = first.foreground;
+                term_state.background = first.background;
+            }
+            term_state.cursor = (x as u16, y as u16);
+            runs += 1;
+        }
+    }
+
+    term_state.buffer = Some(screen);
+    Ok(runs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use super::*;
+
+    fn frame(text: &str) -> Terminal {
+        let mut frame = Terminal::new(8, 2);
+        write!(frame, "{text}").unwrap();
+        frame
+    }
+
+    #[test]
+    fn recording_backend_only_sends_changes() {
+        let mut backend = RecordingBackend::recording().synchronized(false);
+        backend.draw(&frame("abcdefgh")).unwrap();
+        let full = backend.take_bytes();
+        assert_eq!(backend.draw(&frame("abcdefgh")).unwrap(), FrameStats::default());
+        assert!(backend.take_bytes().is_empty());
+        let stats = backend.draw(&frame("abcXefgh")).unwrap();
+        let diff = backend.take_bytes();
+        assert_eq!(stats, FrameStats { bytes: diff.len(), runs: 1, skipped: false });
+        assert!(diff.len() < full.len());
+        assert!(String::from_utf8(diff).unwrap().ends_with('X'));
+    }
+
+    #[test]
+    fn adjacent_changes_are_written_as_one_run() {
+        let mut backend = RecordingBackend::recording().synchronized(false);
+        backend.draw(&frame("abcdefgh")).unwrap();
+        backend.take_bytes();
+        let stats = backend.draw(&frame("aXYZefgQ")).unwrap();
+        let diff = String::from_utf8(backend.take_bytes()).unwrap();
+        assert_eq!(stats.runs, 2);
+        assert!(diff.contains("XYZ"));
+        assert_eq!(diff.matches('\x1b').count(), 2, "expected one cursor move per run: {diff:?}");
+    }
+
+    #[test]
+    fn frames_are_wrapped_in_synchronized_updates() {
+        let mut backend = RecordingBackend::recording();
+        let stats = backend.draw(&frame("abcdefgh")).unwrap();
+        let bytes = String::from_utf8(backend.take_bytes()).unwrap();
+        assert_eq!(stats.bytes, bytes.len());
+        assert!(bytes.starts_with("\x1b[?2026h") && bytes.ends_with("\x1b[?2026l"));
+    }
+
+    #[test]
+    fn recording_backend_repaints_after_invalidate() {
+        let mut backend = RecordingBackend::recording();
+        backend.draw(&frame("abcdefgh")).unwrap();
+        backend.invalidate().unwrap();
+        backend.take_bytes();
+        backend.draw(&frame("abcdefgh")).unwrap();
+        assert!(String::from_utf8(backend.take_bytes()).unwrap().contains("abcdefgh"));
+    }
+
+    #[test]
+    fn bandwidth_limit_spreads_changes_over_frames() {
+        let mut backend = RecordingBackend::recording()
+            .synchronized(false)
+            .bandwidth(200, Duration::from_millis(100));
+        let text = "a b c d\ne f g h\nA B C D\nE F G H";
+        let mut frames = 0;
+        loop {
+            let mut frame = Terminal::new(8, 4);
+            write!(frame, "{text}").unwrap();
+            let stats = backend.draw(&frame).unwrap();
+            if stats.bytes == 0 {
+                break;
+            }
+            assert!(stats.bytes <= 20, "frame of {} bytes exceeds the budget", stats.bytes);
+            frames += 1;
+        }
+        assert!(frames > 1);
+        let screen = backend.state.buffer.as_ref().unwrap();
+        assert_eq!(screen.rows[3][6].data, 'H');
+    }
+
+    #[test]
+    fn memory_backend_keeps_last_frame() {
+        let mut backend = MemoryBackend::default();
+        backend.draw(&frame("first")).unwrap();
+        backend.draw(&frame("second")).unwrap();
+        assert_eq!(backend.frames_drawn, 2);
+        assert_eq!(backend.frame.unwrap().rows[0][0].data, 's');
+    }
+}
@@ -0,0 +1,391 @@
+//! Battle mode: computer-controlled snakes share the level with the player,
+//! go for the same number and die by the same rules.
+//!
+//! A round ends when the player makes a mistake, or when an opponent eats the
+//! last number, which costs a life all the same. It also ends once the player
+//! eats the last number or is the only snake left, which finishes the level.
+//! Opponents only come back for the next round.
+//!
+//! One of them can be a rival player instead, steered from the same keyboard
+//! with the arrow keys and `GameEvent::Second`.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::autopilot;
+use crate::snake::Snake;
+use crate::{Board, Direction, GameState, LIGHT_CYCLE_POINTS, SnakeCell};
+
+/// The most opponents a game can have.
+pub(crate) const MAX_OPPONENTS: usize = 3;
+/// The opponents' colours, in the order they join.
+pub(crate) const COLORS: [i16; MAX_OPPONENTS] = [13, 14, 10];
+
+/// How often opponents don't get round to steering and just go straight on.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard
+}
+
+impl Difficulty {
+    /// The chance of not steering on a tick, as a ratio.
+    fn distraction(self) -> (u32, u32) {
+        match self {
+            Difficulty::Easy => (1, 3),
+            Difficulty::Normal => (1, 10),
+            Difficulty::Hard => (0, 1)
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Difficulty, ()> {
+        match name {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard"
+        })
+    }
+}
+
+/// A computer-controlled snake. Its snake is empty once it's out of the
+/// round.
+#[derive(Serialize)]
+pub(crate) struct Opponent {
+    pub(crate) snake: Snake,
+    direction: Direction,
+    extending: u8,
+    pub(crate) score: i32,
+    difficulty: Difficulty,
+    /// Whether a second player steers rather than the autopilot.
+    human: bool
+}
+
+/// How a tick went for the opponents.
+pub(crate) enum Round {
+    Continues,
+    /// Every opponent is out.
+    Won,
+    /// An opponent ate the last number.
+    Lost
+}
+
+/// Adds `count` opponents to a game that has none yet. They join when the
+/// next round starts.
+pub(crate) fn add_opponents(game_state: &mut GameState, count: usize, difficulty: Difficulty) {
+    game_state.opponents = (0..count.min(MAX_OPPONENTS))
+        .map(|_| Opponent {
+            snake: Snake::default(),
+            direction: Direction::Right,
+            extending: 0,
+            score: 0,
+            difficulty,
+            human: false
+        })
+        .collect();
+}
+
+/// Adds a rival player as the first opponent, in place of the last
+/// computer-controlled one if there's no room otherwise.
+pub(crate) fn add_rival(game_state: &mut GameState) {
+    game_state.opponents.truncate(MAX_OPPONENTS - 1);
+    game_state.opponents.insert(0, Opponent {
+        snake: Snake::default(),
+        direction: Direction::Right,
+        extending: 0,
+        score: 0,
+        difficulty: Difficulty::default(),
+        human: true
+    });
+}
+
+/// How many computer-controlled opponents the game has and how good they
+/// are, if it has any.
+pub(crate) fn setup(game_state: &GameState) -> Option<(usize, Difficulty)> {
+    let mut computers = game_state.opponents.iter().filter(|opponent| !opponent.human);
+    let first = computers.next()?;
+    Some((computers.count() + 1, first.difficulty))
+}
+
+/// Whether the game has a rival player.
+pub(crate) fn rival(game_state: &GameState) -> bool {
+    game_state.opponents.first().is_some_and(|opponent| opponent.human)
+}
+
+/// Turns the rival player's snake, if it's still in the round and can go
+/// that way.
+pub(crate) fn turn_rival(game_state: &mut GameState, direction: Direction) {
+    let Some(rival) = game_state.opponents.first_mut().filter(|opponent| opponent.human && !opponent.snake.is_empty()) else {
+        return;
+    };
+    if let Some(direction) = Direction::get_valid_transition(rival.direction, direction) {
+        rival.direction = direction;
+    }
+}
+
+/// Puts every opponent back on the board for a new round, somewhere away
+/// from the other snakes with a little room ahead.
+pub(crate) fn spawn(game_state: &mut GameState, board: &Board) {
+    let player = game_state.snake.iter().next().copied();
+    let mut taken: Vec<SnakeCell> = game_state.snake.iter().copied().collect();
+    for index in 0..game_state.opponents.len() {
+        let spot = (0..1000).map(|_| {
+            let head = SnakeCell(
+                game_state.rng.gen_range(1..board.width as i32),
+                game_state.rng.gen_range(1..=board.height as i32)
+            );
+            (head, game_state.rng.gen::<Direction>())
+        }).find(|&(head, direction)| {
+            let (dx, dy) = step(direction);
+            let far_from_player = player.is_none_or(|SnakeCell(x, y)| (x - head.0).abs() + (y - head.1).abs() > 6);
+            far_from_player && (-1..=4).all(|ahead| {
+                let cell = SnakeCell(head.0 + dx * ahead, head.1 + dy * ahead);
+                is_free(board, cell) && !taken.contains(&cell)
+            })
+        });
+
+        let opponent = &mut game_state.opponents[index];
+        opponent.extending = 0;
+        // sits the round out if there's no room anywhere
+        let Some((head, direction)) = spot else {
+            opponent.snake.clear();
+            continue;
+        };
+        let (dx, dy) = step(direction);
+        let cells = vec![head, SnakeCell(head.0 - dx, head.1 - dy)];
+        taken.extend(cells.iter().copied());
+        opponent.snake.reset(cells);
+        opponent.direction = direction;
+    }
+}
+
+/// Drops every opponent from the board, for when the round is over.
+pub(crate) fn clear(game_state: &mut GameState) {
+    for opponent in game_state.opponents.iter_mut() {
+        opponent.snake.clear();
+    }
+}
+
+/// Whether all snakes are within the free area of `board`.
+pub(crate) fn fit(game_state: &GameState, board: &Board) -> bool {
+    game_state.opponents.iter().all(|opponent| opponent.snake.iter().all(|&cell| is_free(board, cell)))
+}
+
+/// Whether an opponent is on `cell`, which has to be on `board`.
+pub(crate) fn occupied(game_state: &mut GameState, cell: SnakeCell, board: &Board) -> bool {
+    game_state.opponents.iter_mut().any(|opponent| !opponent.snake.is_empty() && opponent.snake.contains(cell, board))
+}
+
+/// Moves every opponent still in the round by one pixel, after the player
+/// moved.
+pub(crate) fn update(game_state: &mut GameState, board: &Board) -> Round {
+    if game_state.opponents.iter().all(|opponent| opponent.snake.is_empty()) {
+        return Round::Continues;
+    }
+
+    for index in 0..game_state.opponents.len() {
+        if game_state.opponents[index].snake.is_empty() {
+            continue;
+        }
+        steer(game_state, board, index);
+
+        let light_cycle = game_state.light_cycle;
+        let opponent = &mut game_state.opponents[index];
+        if light_cycle {
+            opponent.snake.grow();
+        } else if opponent.extending != 0 {
+            opponent.snake.grow();
+            opponent.extending -= 1;
+        }
+        let (dx, dy) = step(opponent.direction);
+        let SnakeCell(x, y) = opponent.snake.head();
+        let head = SnakeCell(x + dx, y + dy);
+
+        let crashed = !is_free(board, head)
+            || game_state.snake.contains(head, board)
+            || game_state.opponents.iter_mut().enumerate()
+                .any(|(other, opponent)| other != index && !opponent.snake.is_empty() && opponent.snake.contains(head, board));
+        let opponent = &mut game_state.opponents[index];
+        if crashed || !opponent.snake.advance(head, board) {
+            opponent.snake.clear();
+            continue;
+        }
+        if light_cycle {
+            opponent.score += LIGHT_CYCLE_POINTS;
+            continue;
+        }
+
+        let number = game_state.number_pos;
+        if (head.0 as usize, (head.1 - head.1 % 2) as usize) == (number.0, number.1 - number.1 % 2) {
+            opponent.score += game_state.current_number as i32 * 100;
+            if game_state.current_number == 10 {
+                return Round::Lost;
+            }
+            opponent.extending = game_state.current_number * 4;
+            game_state.current_number += 1;
+            game_state.number_pos = (0, 0);
+        }
+    }
+
+    if game_state.opponents.iter().all(|opponent| opponent.snake.is_empty()) {
+        Round::Won
+    } else {
+        Round::Continues
+    }
+}
+
+/// Lets the autopilot turn opponent `index`, unless it's distracted or a
+/// player steers it.
+fn steer(game_state: &mut GameState, board: &Board, index: usize) {
+    if game_state.opponents[index].human {
+        return;
+    }
+    let (numerator, denominator) = game_state.opponents[index].difficulty.distraction();
+    if game_state.rng.gen_ratio(numerator, denominator) {
+        return;
+    }
+    let opponent = &game_state.opponents[index];
+    let others: Vec<&Snake> = std::iter::once(&game_state.snake)
+        .chain(game_state.opponents.iter().enumerate().filter(|&(other, _)| other != index).map(|(_, opponent)| &opponent.snake))
+        .collect();
+    let turn = autopilot::route(game_state, board, &opponent.snake, opponent.direction, opponent.extending, &others);
+    if let Some(direction) = turn.and_then(|turn| Direction::get_valid_transition(opponent.direction, turn)) {
+        game_state.opponents[index].direction = direction;
+    }
+}
+
+fn step(direction: Direction) -> (i32, i32) {
+    match direction {
+        Direction::Up | Direction::Down => (0, direction.as_integer()),
+        Direction::Left | Direction::Right => (direction.as_integer(), 0)
+    }
+}
+
+/// The same bounds and walls `update` checks the player's head against.
+fn is_free(board: &Board, SnakeCell(x, y): SnakeCell) -> bool {
+    x >= 1 && y >= 1 && x < board.width as i32 && y <= board.height as i32
+        && board.lookup(x as usize, y as usize) < 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameEvent, advance, create_game_state, game_over, shown_board};
+
+    fn battle(seed: u64, count: usize, difficulty: Difficulty) -> GameState {
+        let mut game_state = create_game_state(seed, (80, 24));
+        add_opponents(&mut game_state, count, difficulty);
+        shown_board(&mut game_state);
+        game_state
+    }
+
+    #[test]
+    fn opponents_spawn_apart_on_free_pixels() {
+        for seed in 0..50 {
+            let mut game_state = battle(seed, 3, Difficulty::Normal);
+            let board = shown_board(&mut game_state);
+            let mut cells: Vec<SnakeCell> = game_state.snake.iter().copied().collect();
+            for opponent in game_state.opponents.iter() {
+                assert_eq!(opponent.snake.iter().count(), 2, "seed {seed}");
+                cells.extend(opponent.snake.iter().copied());
+            }
+            assert!(cells.iter().all(|&cell| is_free(&board, cell)), "seed {seed}");
+            let mut unique = cells.clone();
+            unique.sort_by_key(|&SnakeCell(x, y)| (x, y));
+            unique.dedup();
+            assert_eq!(unique.len(), cells.len(), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn rounds_end_and_opponents_return() {
+        // the player never steers, so the round ends with a mistake or an
+        // opponent taking the level, and every round starts with all of them
+        let mut game_state = battle(4, 2, Difficulty::Hard);
+        let mut rounds = 0;
+        while !game_over(&game_state) && rounds < 20 {
+            if game_state.paused {
+                advance(GameEvent::Action, &mut game_state);
+                assert!(game_state.opponents.iter().all(|opponent| !opponent.snake.is_empty()));
+                rounds += 1;
+            }
+            advance(GameEvent::Timeout, &mut game_state);
+        }
+        assert!(game_over(&game_state));
+        assert!(game_state.opponents.iter().any(|opponent| opponent.score > 0), "hard opponents should eat");
+    }
+
+    #[test]
+    fn the_last_snake_standing_wins_the_round() {
+        let mut game_state = battle(9, 1, Difficulty::Normal);
+        advance(GameEvent::Action, &mut game_state);
+        // an opponent stuck in the top right corner with its body below its
+        // head has nowhere to go
+        let board = shown_board(&mut game_state);
+        let x = board.width as i32 - 1;
+        let opponent = &mut game_state.opponents[0];
+        opponent.snake.reset(vec![SnakeCell(x, 1), SnakeCell(x - 1, 1), SnakeCell(x - 1, 2), SnakeCell(x, 2), SnakeCell(x, 3)]);
+        opponent.direction = Direction::Right;
+        let level = game_state.level;
+        advance(GameEvent::Timeout, &mut game_state);
+        assert_eq!(game_state.level, level + 1);
+        assert_eq!(game_state.lives, 5);
+    }
+
+    #[test]
+    fn light_cycle_trails_never_shrink() {
+        let mut game_state = create_game_state(5, (80, 24));
+        add_opponents(&mut game_state, 2, Difficulty::Hard);
+        game_state.light_cycle = true;
+        advance(GameEvent::Action, &mut game_state);
+        let length = game_state.snake.iter().count();
+        for tick in 1..=5 {
+            advance(GameEvent::Timeout, &mut game_state);
+            assert_eq!(game_state.snake.iter().count(), length + tick);
+            assert_eq!(game_state.score, tick as i32 * crate::LIGHT_CYCLE_POINTS);
+        }
+        assert_eq!(game_state.number_pos, (0, 0), "there's nothing to eat");
+
+        // the opponents keep out of every trail for as long as they can
+        while !game_state.paused {
+            advance(GameEvent::Timeout, &mut game_state);
+        }
+        assert!(game_state.opponents.iter().all(|opponent| opponent.score > 0));
+    }
+
+    #[test]
+    fn the_rival_is_steered_by_the_second_player() {
+        let mut game_state = create_game_state(6, (80, 24));
+        add_opponents(&mut game_state, 3, Difficulty::Normal);
+        add_rival(&mut game_state);
+        assert_eq!(game_state.opponents.len(), MAX_OPPONENTS);
+        assert_eq!(setup(&game_state).map(|(count, _)| count), Some(MAX_OPPONENTS - 1));
+        shown_board(&mut game_state);
+        advance(GameEvent::Action, &mut game_state);
+
+        let (heading, player_heading) = (game_state.opponents[0].direction, game_state.snake_direction);
+        let key = if matches!(heading, Direction::Up | Direction::Down) { crate::race::Key::Left } else { crate::race::Key::Up };
+        advance(GameEvent::Second(key), &mut game_state);
+        assert!(game_state.opponents[0].direction == key.direction().unwrap());
+        assert!(game_state.snake_direction == player_heading, "the player's snake goes on");
+    }
+}
@@ -0,0 +1,53 @@
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, Instant};
+
+use crate::backend::{Backend, RecordingBackend};
+use crate::render::Renderer;
+use crate::{GameEvent, GameRng, advance, create_game_state};
+
+const SIZE: (u16, u16) = (300, 100);
+const FRAMES: usize = 2000;
+
+/// Plays a game on a 300x100 terminal without a tty, turning at random and
+/// unpausing after every mistake, and prints what each frame cost to render
+/// and to diff into escape sequences.
+pub(crate) fn run() {
+    let mut game_state = create_game_state(0x62_656e_6368, SIZE);
+    let mut rng = GameRng::seed_from_u64(game_state.seed);
+    let mut renderer = Renderer::new();
+    let mut backend = RecordingBackend::recording();
+
+    let (mut render_times, mut diff_times) = (Vec::with_capacity(FRAMES), Vec::with_capacity(FRAMES));
+    let mut bytes = 0;
+    for _ in 0..FRAMES {
+        if game_state.paused {
+            advance(GameEvent::Action, &mut game_state);
+        }
+        if rng.gen_ratio(1, 8) {
+            let turn = [GameEvent::Up, GameEvent::Right, GameEvent::Down, GameEvent::Left][rng.gen_range(0..4)];
+            advance(turn, &mut game_state);
+        }
+        let Some(board) = advance(GameEvent::Timeout, &mut game_state) else {
+            continue;
+        };
+
+        let start = Instant::now();
+        let frame = renderer.render(&game_state, &board);
+        let rendered = Instant::now();
+        bytes += backend.draw(frame).unwrap().bytes;
+        render_times.push(rendered - start);
+        diff_times.push(rendered.elapsed());
+        backend.take_bytes();
+    }
+
+    println!("{} frames at {}x{}, {} bytes per frame", render_times.len(), SIZE.0, SIZE.1, bytes / render_times.len());
+    println!("render: {}", summary(&mut render_times));
+    println!("diff:   {}", summary(&mut diff_times));
+}
+
+fn summary(times: &mut [Duration]) -> String {
+    times.sort();
+    let mean = times.iter().sum::<Duration>() / times.len() as u32;
+    let percentile = |p: usize| times[(times.len() - 1) * p / 100];
+    format!("mean {:?}, p50 {:?}, p99 {:?}, max {:?}", mean, percentile(50), percentile(99), times[times.len() - 1])
+}
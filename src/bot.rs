@@ -0,0 +1,276 @@
+//! Lets an external program play. Every tick the game writes the state as a
+//! line of JSON and waits, up to a deadline, for a line with a direction:
+//!
+//! ```text
+//! > {"tick":0,"width":78,"height":44,"walls":[[19,1],...],"snake":[[41,22],[40,22]],
+//!    "direction":"right","food":{"x":12,"y":30,"number":1},"score":0,"lives":5,"level":1}
+//! < {"direction":"up"}
+//! ```
+//!
+//! The snake's head may go anywhere from `1` to `width - 1` and from `1` to
+//! `height` that isn't a wall. The food covers `(x, y)` and `(x, y + 1)`.
+//! A reply without a direction, or none by the deadline, keeps the snake going
+//! straight; replies are matched to states in order, so a late one is dropped
+//! once it arrives. Turning back is ignored, as it is for keys.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::events::wait_readable;
+use crate::{Board, Direction, GameEvent, GameState, advance, create_game_state, game_over, shown_board};
+
+/// The terminal the games are laid out for.
+const SIZE: (u16, u16) = (80, 24);
+/// Gives up on a game once the bot went this long without eating, since it
+/// would otherwise go round in circles forever.
+const STARVATION_TICKS: u64 = 10_000;
+
+pub(crate) enum Connection {
+    /// A command run by `sh`, spoken to over its stdin and stdout.
+    Command(String),
+    /// A Unix socket the bot listens on.
+    Socket(PathBuf)
+}
+
+#[derive(Serialize)]
+struct State<'a> {
+    tick: u64,
+    width: usize,
+    height: usize,
+    walls: &'a [(usize, usize)],
+    snake: Vec<(i32, i32)>,
+    direction: &'static str,
+    food: Food,
+    score: i32,
+    lives: u8,
+    level: u8
+}
+
+#[derive(Serialize)]
+struct Food {
+    x: usize,
+    y: usize,
+    number: u8
+}
+
+#[derive(Deserialize)]
+struct Reply {
+    #[serde(default)]
+    direction: Option<String>
+}
+
+/// What a bot achieved in one game.
+struct Outcome {
+    seed: u64,
+    score: i32,
+    level: u8,
+    ticks: u64,
+    late: u64,
+    reply_time: Duration
+}
+
+/// Plays `games` games with the bot on the other end of `connection`, without
+/// a terminal, and prints how it did.
+pub(crate) fn run(connection: Connection, deadline: Duration, games: u32) -> io::Result<()> {
+    let mut bot = Bot::connect(connection)?;
+    let mut outcomes = Vec::new();
+    for game in 1..=games {
+        let outcome = play(&mut bot, deadline)?;
+        println!("game {game}: seed {:016x}, score {}, level {}, {} ticks, {} late replies, {:?} per reply",
+                 outcome.seed, outcome.score, outcome.level, outcome.ticks, outcome.late,
+                 outcome.reply_time / outcome.ticks.max(1) as u32);
+        outcomes.push(outcome);
+    }
+    let mean_score = outcomes.iter().map(|outcome| outcome.score as i64).sum::<i64>() / outcomes.len().max(1) as i64;
+    println!("mean score {mean_score} over {} games", outcomes.len());
+    Ok(())
+}
+
+fn play(bot: &mut Bot, deadline: Duration) -> io::Result<Outcome> {
+    let mut game_state = create_game_state(rand::random(), SIZE);
+    let (mut late, mut reply_time) = (0, Duration::ZERO);
+    let mut walls = Vec::new();
+    let mut walls_of = None;
+    let (mut last_score, mut last_meal) = (game_state.score, 0);
+
+    while game_state.ticks - last_meal < STARVATION_TICKS {
+        if game_state.paused {
+            advance(GameEvent::Action, &mut game_state);
+        }
+        let board = shown_board(&mut game_state);
+        // the walls only change with the level
+        if !walls_of.as_ref().is_some_and(|walls_of| Rc::ptr_eq(walls_of, &board)) {
+            walls = wall_cells(&board);
+            walls_of = Some(Rc::clone(&board));
+        }
+
+        let start = Instant::now();
+        bot.send(&state(&game_state, &board, &walls))?;
+        match bot.receive(start + deadline)? {
+            Some(Some(direction)) => advance(direction, &mut game_state),
+            Some(None) => None,
+            None => {
+                late += 1;
+                None
+            }
+        };
+        reply_time += start.elapsed();
+
+        advance(GameEvent::Timeout, &mut game_state);
+        if game_over(&game_state) {
+            break;
+        }
+        if game_state.score > last_score {
+            last_meal = game_state.ticks;
+        }
+        last_score = game_state.score;
+    }
+
+    Ok(Outcome {
+        seed: game_state.seed,
+        score: game_state.score,
+        level: game_state.level,
+        ticks: game_state.ticks,
+        late,
+        reply_time
+    })
+}
+
+fn wall_cells(board: &Board) -> Vec<(usize, usize)> {
+    (1..=board.height)
+        .flat_map(|y| (1..board.width).map(move |x| (x, y)))
+        .filter(|&(x, y)| board.lookup(x, y) >= 0)
+        .collect()
+}
+
+fn state<'a>(game_state: &GameState, board: &Board, walls: &'a [(usize, usize)]) -> State<'a> {
+    let (x, y) = game_state.number_pos;
+    State {
+        tick: game_state.ticks,
+        width: board.width,
+        height: board.height,
+        walls,
+        snake: game_state.snake.iter().map(|cell| (cell.0, cell.1)).collect(),
+        direction: direction_name(game_state.snake_direction),
+        food: Food { x, y: y - y % 2, number: game_state.current_number },
+        score: game_state.score,
+        lives: game_state.lives,
+        level: game_state.level
+    }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Up => "up",
+        Direction::Right => "right",
+        Direction::Down => "down",
+        Direction::Left => "left"
+    }
+}
+
+/// The bot process or socket, and how many replies it still owes us for
+/// states whose deadline has passed.
+struct Bot {
+    child: Option<Child>,
+    input: Box<dyn Write>,
+    output: BufReader<Box<dyn ReadFd>>,
+    owed: u64
+}
+
+trait ReadFd: Read + AsRawFd {}
+
+impl<T: Read + AsRawFd> ReadFd for T {}
+
+impl Bot {
+    fn connect(connection: Connection) -> io::Result<Bot> {
+        match connection {
+            Connection::Command(command) => {
+                let mut child = Command::new("sh")
+                    .args(["-c", &command])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+                let input = Box::new(child.stdin.take().unwrap());
+                let output: Box<dyn ReadFd> = Box::new(child.stdout.take().unwrap());
+                Ok(Bot { child: Some(child), input, output: BufReader::new(output), owed: 0 })
+            }
+            Connection::Socket(path) => {
+                let stream = UnixStream::connect(path)?;
+                let input = Box::new(stream.try_clone()?);
+                let output: Box<dyn ReadFd> = Box::new(stream);
+                Ok(Bot { child: None, input, output: BufReader::new(output), owed: 0 })
+            }
+        }
+    }
+
+    fn send(&mut self, state: &State) -> io::Result<()> {
+        let mut line = serde_json::to_vec(state)?;
+        line.push(b'\n');
+        self.input.write_all(&line)?;
+        self.input.flush()?;
+        self.owed += 1;
+        Ok(())
+    }
+
+    /// The direction the bot replied with to the latest state, if any, or
+    /// `None` if it didn't reply by `deadline`.
+    fn receive(&mut self, deadline: Instant) -> io::Result<Option<Option<GameEvent>>> {
+        while self.owed > 0 {
+            if self.output.buffer().is_empty() && !wait_readable(self.output.get_ref().as_raw_fd(), deadline)? {
+                return Ok(None);
+            }
+            // a line may come in pieces, but once it started the rest won't be
+            // long; we only wait for the deadline before the first byte
+            let mut line = String::new();
+            if self.output.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the bot hung up"));
+            }
+            self.owed -= 1;
+            if self.owed == 0 {
+                return parse_reply(&line).map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn parse_reply(line: &str) -> io::Result<Option<GameEvent>> {
+    let reply: Reply = serde_json::from_str(line)?;
+    match reply.direction.as_deref() {
+        None => Ok(None),
+        Some("up") => Ok(Some(GameEvent::Up)),
+        Some("right") => Ok(Some(GameEvent::Right)),
+        Some("down") => Ok(Some(GameEvent::Down)),
+        Some("left") => Ok(Some(GameEvent::Left)),
+        Some(other) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown direction {other:?}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_map_to_turns() {
+        assert!(matches!(parse_reply("{\"direction\":\"up\"}\n"), Ok(Some(GameEvent::Up))));
+        assert!(matches!(parse_reply("{\"direction\":null}"), Ok(None)));
+        assert!(matches!(parse_reply("{}"), Ok(None)));
+        assert!(parse_reply("{\"direction\":\"north\"}").is_err());
+        assert!(parse_reply("up").is_err());
+    }
+}
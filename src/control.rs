@@ -0,0 +1,341 @@
+//! A Unix socket for tooling to drive a running game and follow along, one
+//! line of JSON per message. Clients send commands and get a reply to each:
+//!
+//! ```text
+//! > {"command":"pause"}
+//! < {"ok":true}
+//! > {"command":"query-state"}
+//! < {"ok":true,"state":{"tick":130,"score":300,"lives":5,"level":1,"paused":true,...}}
+//! > {"command":"inject-event","event":"Up"}
+//! < {"ok":true}
+//! ```
+//!
+//! `resume` goes on after a pause or a mistake, but doesn't start a new game
+//! once the last life is gone; injecting `Action` does. Only the keys of a
+//! game can be injected, spelled as in crash bundles: `"Up"`, `"Down"`,
+//! `"Left"`, `"Right"`, `"Action"` and `"Escape"`. They're played as if they
+//! came from the keyboard.
+//!
+//! Every client also gets what happens in the game as it happens, told apart
+//! from replies by their `event`:
+//!
+//! ```text
+//! < {"event":"food","number":3,"score":600}
+//! < {"event":"life_lost","lives":4,"score":-400}
+//! < {"event":"level","level":2,"score":5600}
+//! ```
+
+use mio::net::{UnixListener, UnixStream};
+use mio::{Interest, Registry, Token};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::events::{Peer, Slots, accept_all, receive, send};
+use crate::{GameEvent, GameState, game_over};
+
+/// How much may wait for a client that doesn't read. Past that it's dropped.
+const MAX_BACKLOG: usize = 64 * 1024;
+/// The longest line we wait for the end of.
+const MAX_LINE: usize = 4096;
+
+/// Something that happened in the game, as told to control clients.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Notice {
+    Food { number: u8, score: i32 },
+    /// A mistake. Without lives left, the game is over.
+    LifeLost { lives: u8, score: i32 },
+    Level { level: u8, score: i32 }
+}
+
+/// Keeps `notice` for the control clients, if there are any.
+pub(crate) fn notify(game_state: &mut GameState, notice: Notice) {
+    if let Some(notices) = &mut game_state.notices {
+        notices.push(notice);
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Command {
+    Pause,
+    Resume,
+    QueryState,
+    InjectEvent { event: GameEvent }
+}
+
+#[derive(Serialize)]
+struct Reply<'a> {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<State>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>
+}
+
+#[derive(Serialize)]
+struct State {
+    tick: u64,
+    score: i32,
+    lives: u8,
+    level: u8,
+    paused: bool,
+    game_over: bool,
+    /// The number to eat next.
+    number: u8,
+    length: usize,
+    opponent_scores: Vec<i32>
+}
+
+/// The control socket and its clients.
+pub(crate) struct Control {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Slots<Client>
+}
+
+struct Client {
+    stream: UnixStream,
+    /// What was read after the last complete line.
+    input: Vec<u8>,
+    /// Complete lines waiting for the game to answer them.
+    requests: Vec<String>,
+    /// Output the client hasn't taken yet.
+    backlog: Vec<u8>
+}
+
+impl Control {
+    pub(crate) fn bind(path: &Path) -> io::Result<Control> {
+        Ok(Control { listener: UnixListener::bind(path)?, path: path.to_path_buf(), clients: Slots::new() })
+    }
+
+    /// Answers the commands that came in since the last call. Events they ask
+    /// for go into `out`, to be played with the ones from the keyboard.
+    pub(crate) fn serve(&mut self, game_state: &GameState, out: &mut Vec<GameEvent>) {
+        self.clients.retain(|client| {
+            for request in std::mem::take(&mut client.requests) {
+                let reply = match serde_json::from_str(&request) {
+                    Ok(command) => answer(command, game_state, out),
+                    Err(_) => Reply { ok: false, state: None, error: Some("not a command") }
+                };
+                client.send(&reply);
+            }
+            client.flush().is_ok()
+        });
+    }
+
+    /// Tells every client what happened in the game since the last call.
+    pub(crate) fn publish(&mut self, game_state: &mut GameState) {
+        let Some(notices) = game_state.notices.as_mut().filter(|notices| !notices.is_empty()) else {
+            return;
+        };
+        self.clients.retain(|client| {
+            for notice in notices.iter() {
+                client.send(notice);
+            }
+            client.flush().is_ok()
+        });
+        notices.clear();
+    }
+
+    fn accept(&mut self, registry: &Registry, base: usize) {
+        // most likely out of file descriptors, which may pass
+        let _ = accept_all(|| self.listener.accept(), |(mut stream, _)| {
+            let index = self.clients.vacant();
+            if registry.register(&mut stream, Token(base + 1 + index), Interest::READABLE | Interest::WRITABLE).is_err() {
+                return;
+            }
+            self.clients.insert(index, Client { stream, input: Vec::new(), requests: Vec::new(), backlog: Vec::new() });
+        });
+    }
+
+    /// Reads what client `index` sent and sends what it can take.
+    fn ready(&mut self, registry: &Registry, index: usize) {
+        let Some(client) = self.clients.get_mut(index) else {
+            return;
+        };
+        if client.receive().is_err() || client.flush().is_err() {
+            let _ = registry.deregister(&mut client.stream);
+            self.clients.remove(index);
+        }
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn answer(command: Command, game_state: &GameState, out: &mut Vec<GameEvent>) -> Reply<'static> {
+    let ok = Reply { ok: true, state: None, error: None };
+    match command {
+        // only pauses a running game, like the key
+        Command::Pause => out.push(GameEvent::Escape),
+        Command::Resume if game_over(game_state) => {
+            return Reply { ok: false, state: None, error: Some("the game is over") };
+        }
+        Command::Resume => {
+            if game_state.paused {
+                out.push(GameEvent::Action);
+            }
+        }
+        Command::QueryState => return Reply { state: Some(state(game_state)), ..ok },
+        // quitting, suspending or resizing is up to the player at the terminal
+        Command::InjectEvent { event: event @ (GameEvent::Up | GameEvent::Down | GameEvent::Left | GameEvent::Right | GameEvent::Action | GameEvent::Escape) } => {
+            out.push(event);
+        }
+        Command::InjectEvent { .. } => {
+            return Reply { ok: false, state: None, error: Some("only keys of the game can be injected") };
+        }
+    }
+    ok
+}
+
+fn state(game_state: &GameState) -> State {
+    State {
+        tick: game_state.ticks,
+        score: game_state.score,
+        lives: game_state.lives,
+        level: game_state.level,
+        paused: game_state.paused,
+        game_over: game_over(game_state),
+        number: game_state.current_number,
+        length: game_state.snake.iter().count(),
+        opponent_scores: game_state.opponents.iter().map(|opponent| opponent.score).collect()
+    }
+}
+
+impl Client {
+    /// Reads everything there is. Fails once the client hung up.
+    fn receive(&mut self) -> io::Result<()> {
+        if !receive(&mut self.stream, &mut self.input)? {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        while let Some(end) = self.input.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.input.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if !line.is_empty() {
+                self.requests.push(line);
+            }
+        }
+        if self.input.len() > MAX_LINE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, message: &impl Serialize) {
+        // serializing our own types into memory can't fail
+        serde_json::to_writer(&mut self.backlog, message).unwrap();
+        self.backlog.push(b'\n');
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        send(&mut self.stream, &mut self.backlog)?;
+        if self.backlog.len() > MAX_BACKLOG {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "not reading"));
+        }
+        Ok(())
+    }
+}
+
+/// Lets clients in and takes their commands, for the game loop to answer.
+pub(crate) struct ControlSocket {
+    control: Rc<RefCell<Control>>,
+    base: usize
+}
+
+impl ControlSocket {
+    pub(crate) fn new(control: Rc<RefCell<Control>>) -> ControlSocket {
+        ControlSocket { control, base: 0 }
+    }
+}
+
+impl Peer for ControlSocket {
+    fn register(&mut self, registry: &Registry, base: usize) -> io::Result<()> {
+        self.base = base;
+        registry.register(&mut self.control.borrow_mut().listener, Token(base), Interest::READABLE)
+    }
+
+    fn ready(&mut self, registry: &Registry, offset: usize, _: &mut Vec<GameEvent>) -> io::Result<()> {
+        let mut control = self.control.borrow_mut();
+        match offset {
+            0 => control.accept(registry, self.base),
+            offset => control.ready(registry, offset - 1)
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{advance, create_game_state};
+
+    fn play(commands: &[&str], game_state: &mut GameState) -> Vec<serde_json::Value> {
+        let mut out = Vec::new();
+        let replies = commands.iter().map(|command| {
+            let reply = match serde_json::from_str(command) {
+                Ok(command) => answer(command, game_state, &mut out),
+                Err(_) => Reply { ok: false, state: None, error: Some("not a command") }
+            };
+            serde_json::to_value(reply).unwrap()
+        }).collect();
+        for event in out {
+            advance(event, game_state);
+        }
+        replies
+    }
+
+    #[test]
+    fn commands_pause_resume_and_steer() {
+        let mut game_state = create_game_state(1, (80, 24));
+        let replies = play(&[r#"{"command":"resume"}"#, r#"{"command":"inject-event","event":"Up"}"#], &mut game_state);
+        assert!(replies.iter().all(|reply| reply["ok"] == true));
+        assert!(!game_state.paused);
+        assert!(matches!(game_state.snake_direction, crate::Direction::Up | crate::Direction::Down));
+
+        let replies = play(&[r#"{"command":"pause"}"#, r#"{"command":"query-state"}"#, "{}"], &mut game_state);
+        assert!(game_state.paused);
+        assert_eq!(replies[1]["state"]["lives"], 5);
+        assert_eq!(replies[2]["ok"], false);
+
+        game_state.lives = 0;
+        let replies = play(&[r#"{"command":"resume"}"#], &mut game_state);
+        assert_eq!(replies[0]["error"], "the game is over");
+        assert!(game_over(&game_state), "resuming mustn't start a new game");
+    }
+
+    #[test]
+    fn only_keys_of_the_game_are_injected() {
+        let mut game_state = create_game_state(1, (80, 24));
+        let replies = play(&[
+            r#"{"command":"inject-event","event":{"Resize":[0,0]}}"#,
+            r#"{"command":"inject-event","event":"Suspend"}"#,
+            r#"{"command":"inject-event","event":"Quit"}"#
+        ], &mut game_state);
+        assert!(replies.iter().all(|reply| reply["error"] == "only keys of the game can be injected"));
+        assert_eq!(game_state.terminal_size, (80, 24));
+    }
+
+    #[test]
+    fn mistakes_and_meals_are_noticed() {
+        let mut game_state = create_game_state(2, (80, 24));
+        game_state.notices = Some(Vec::new());
+        advance(GameEvent::Action, &mut game_state);
+        // the snake runs into something sooner or later without steering
+        while game_state.lives == 5 {
+            advance(GameEvent::Timeout, &mut game_state);
+        }
+        let notices: Vec<_> = game_state.notices.take().unwrap().iter().map(|notice| serde_json::to_value(notice).unwrap()).collect();
+        let last = notices.last().unwrap();
+        assert_eq!(last["event"], "life_lost");
+        assert_eq!(last["lives"], 4);
+        assert_eq!(last["score"], game_state.score);
+    }
+}
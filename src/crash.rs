@@ -0,0 +1,211 @@
+use serde::{Serialize, Deserialize};
+use std::backtrace::Backtrace;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, BufReader};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::backend::RecordingBackend;
+use crate::battle::{self, Difficulty};
+use crate::modes::Mode;
+use crate::render::Renderer;
+use crate::{GameEvent, GameState, InputRecord, create_game_state, data::data_dir, handle_event, procedural};
+
+/// The panic message and backtrace of the last panic, stashed by the hook so
+/// `write_bundle` can pick them up once the stack has unwound back to `main`.
+static LAST_PANIC: Mutex<Option<(String, String)>> = Mutex::new(None);
+
+#[derive(Serialize)]
+struct CrashBundle<'a> {
+    panic: &'a str,
+    backtrace: &'a str,
+    terminal_size: (u16, u16),
+    seed: u64,
+    ticks: u64,
+    opponents: usize,
+    difficulty: Difficulty,
+    rival: bool,
+    light_cycle: bool,
+    mode: Mode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    level: Option<String>,
+    input_log: &'a [InputRecord],
+    game_state: &'a GameState
+}
+
+/// The part of a `CrashBundle` needed to replay the run, which is also what
+/// a leaderboard needs to check a score.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Replay {
+    pub(crate) seed: u64,
+    pub(crate) ticks: u64,
+    #[serde(default)]
+    pub(crate) opponents: usize,
+    #[serde(default)]
+    pub(crate) difficulty: Difficulty,
+    #[serde(default)]
+    pub(crate) rival: bool,
+    #[serde(default)]
+    pub(crate) light_cycle: bool,
+    #[serde(default)]
+    pub(crate) mode: Mode,
+    /// The level file the game was played on, see `procedural::level_text`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) level: Option<String>,
+    pub(crate) input_log: Vec<InputRecord>
+}
+
+impl Replay {
+    /// The replay of the run in `game_state` so far.
+    pub(crate) fn of(game_state: &GameState) -> Replay {
+        let (opponents, difficulty) = battle::setup(game_state).unwrap_or_default();
+        Replay {
+            seed: game_state.seed,
+            ticks: game_state.ticks,
+            opponents,
+            difficulty,
+            rival: battle::rival(game_state),
+            light_cycle: game_state.light_cycle,
+            mode: game_state.mode,
+            level: game_state.level_file.as_deref().map(procedural::level_text),
+            input_log: game_state.input_log.clone()
+        }
+    }
+
+    /// Plays the run again from the start, handing every recorded event and
+    /// every tick to `play` in the order they happened, and returns the state
+    /// it ends in.
+    pub(crate) fn run(&self, mut play: impl FnMut(GameEvent, &mut GameState)) -> io::Result<GameState> {
+        let Some(&InputRecord { event: GameEvent::Resize(width, height), .. }) = self.input_log.first() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "input log does not start with the terminal size"));
+        };
+        let mut game_state = create_game_state(self.seed, (width, height));
+        battle::add_opponents(&mut game_state, self.opponents, self.difficulty);
+        if self.rival {
+            battle::add_rival(&mut game_state);
+        }
+        game_state.light_cycle = self.light_cycle;
+        game_state.mode = self.mode;
+        if let Some(text) = &self.level {
+            let level = procedural::parse_level(text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            game_state.level_file = Some(Rc::new(level));
+        }
+        game_state.input_log.clear();
+
+        let mut records = self.input_log.iter().peekable();
+        loop {
+            while let Some(record) = records.next_if(|record| record.tick == game_state.ticks) {
+                play(record.event, &mut game_state);
+            }
+            if game_state.ticks >= self.ticks {
+                break;
+            }
+            play(GameEvent::Timeout, &mut game_state);
+        }
+        Ok(game_state)
+    }
+}
+
+pub(crate) fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let backtrace = Backtrace::force_capture();
+        if let Ok(mut last_panic) = LAST_PANIC.lock() {
+            *last_panic = Some((info.to_string(), backtrace.to_string()));
+        }
+        default_hook(info);
+    }));
+}
+
+/// Writes everything needed to reproduce the current run into a new file in
+/// the data directory and returns its path.
+pub(crate) fn write_bundle(game_state: &GameState) -> io::Result<PathBuf> {
+    write_bundle_in(&data_dir()?.join("crashes"), game_state)
+}
+
+fn write_bundle_in(dir: &Path, game_state: &GameState) -> io::Result<PathBuf> {
+    let last_panic = LAST_PANIC.lock().ok().and_then(|mut last_panic| last_panic.take());
+    let (panic, backtrace) = last_panic.unwrap_or_default();
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("crash-{timestamp}-{:016x}.json", game_state.seed));
+
+    let (opponents, difficulty) = battle::setup(game_state).unwrap_or_default();
+    let bundle = CrashBundle {
+        panic: &panic,
+        backtrace: &backtrace,
+        terminal_size: game_state.terminal_size,
+        seed: game_state.seed,
+        ticks: game_state.ticks,
+        opponents,
+        difficulty,
+        rival: battle::rival(game_state),
+        light_cycle: game_state.light_cycle,
+        mode: game_state.mode,
+        level: game_state.level_file.as_deref().map(procedural::level_text),
+        input_log: &game_state.input_log,
+        game_state
+    };
+    let writer = BufWriter::new(File::create(&path)?);
+    serde_json::to_writer_pretty(writer, &bundle)?;
+    Ok(path)
+}
+
+/// Re-simulates the run recorded in a crash bundle without a terminal,
+/// rendering and diffing every frame in memory so panics while drawing
+/// reproduce as well.
+pub(crate) fn replay(path: &Path) -> io::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let replay: Replay = serde_json::from_reader(reader)?;
+
+    let mut renderer = Renderer::new();
+    let mut backend = RecordingBackend::recording();
+    let (mut bytes_written, mut largest_frame) = (0, 0);
+    let game_state = replay.run(|event, game_state| {
+        handle_event(event, game_state, &mut renderer, &mut backend);
+        let frame_bytes = backend.take_bytes().len();
+        bytes_written += frame_bytes;
+        largest_frame = largest_frame.max(frame_bytes);
+    })?;
+
+    println!(
+        "replayed {} ticks ({} bytes of output, largest frame {} bytes) without panicking: level {}, score {}, lives {}",
+        game_state.ticks, bytes_written, largest_frame, game_state.level, game_state.score, game_state.lives
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advance;
+
+    #[test]
+    fn bundles_carry_the_panic_and_replay_to_the_same_game() {
+        let mut game_state = create_game_state(7, (80, 24));
+        advance(GameEvent::Action, &mut game_state);
+        for tick in 0..60 {
+            let turn = if tick % 20 < 10 { GameEvent::Up } else { GameEvent::Right };
+            advance(turn, &mut game_state);
+            advance(GameEvent::Timeout, &mut game_state);
+        }
+        *LAST_PANIC.lock().unwrap() = Some(("boom".to_string(), "backtrace".to_string()));
+
+        let dir = std::env::temp_dir().join(format!("nibbles-crashes-{}", std::process::id()));
+        let path = write_bundle_in(&dir, &game_state).unwrap();
+        let bundle: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!(bundle["panic"], "boom");
+        assert_eq!(bundle["terminal_size"], serde_json::json!([80, 24]));
+
+        let replay: Replay = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        let replayed = replay.run(|event, game_state| { advance(event, game_state); }).unwrap();
+        assert_eq!((replayed.ticks, replayed.score, replayed.lives, replayed.number_pos), (game_state.ticks, game_state.score, game_state.lives, game_state.number_pos));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
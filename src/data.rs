@@ -0,0 +1,21 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Directory for everything we persist between runs, following the XDG base
+/// directory spec (`$XDG_DATA_HOME/nibbles`, falling back to
+/// `~/.local/share/nibbles`). Created on first use.
+pub(crate) fn data_dir() -> io::Result<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = env::var_os("HOME")
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+            PathBuf::from(home).join(".local").join("share")
+        }
+    };
+    let dir = base.join("nibbles");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
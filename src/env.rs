@@ -0,0 +1,356 @@
+//! A headless environment for reinforcement learning on top of `advance`:
+//! `reset(seed)` starts an episode, `step(action)` plays one tick and reports
+//! the observation, the reward and whether the episode is over.
+//!
+//! Observations are `CHANNELS` planes of `width * height` bytes each, laid
+//! out row by row, covering every pixel of the board including the row and
+//! column the snake can never enter:
+//!
+//! - `TILES`: 1 for walls and out of bounds, 0 for free pixels
+//! - `SNAKE`: 2 for the head, 1 for the rest of the body
+//! - `FOOD`: the number to eat, on both of its pixels
+
+use rand::{Rng, SeedableRng};
+use std::rc::Rc;
+
+use crate::{Board, Direction, GameEvent, GameRng, GameState, SnakeCell, advance, create_game_state, game_over, shown_board};
+
+pub const CHANNELS: usize = 3;
+pub const TILES: usize = 0;
+pub const SNAKE: usize = 1;
+pub const FOOD: usize = 2;
+
+/// Ends an episode in which nothing was eaten for this many steps, as a
+/// policy that learned to go round in circles would never die.
+const STARVATION_STEPS: u64 = 5_000;
+
+/// What each outcome of a step is worth. Every step earns `step`, plus one of
+/// the others: `death` for a mistake, `level` for the last number of a level,
+/// `food` for any other number, and otherwise `approach` times how many
+/// pixels closer to the number the snake got (negative if it moved away).
+#[derive(Clone, Copy)]
+pub struct Rewards {
+    pub food: f32,
+    pub level: f32,
+    pub death: f32,
+    pub step: f32,
+    pub approach: f32
+}
+
+impl Default for Rewards {
+    fn default() -> Rewards {
+        Rewards { food: 1.0, level: 5.0, death: -1.0, step: 0.0, approach: 0.0 }
+    }
+}
+
+/// One game to learn on, played a tick per step.
+pub struct Env {
+    size: (u16, u16),
+    rewards: Rewards,
+    end_on_death: bool,
+    game_state: GameState,
+    /// The board `tiles` was derived from.
+    board: Rc<Board>,
+    tiles: Vec<u8>,
+    observation: Vec<u8>,
+    last_meal: u64
+}
+
+impl Env {
+    /// An environment with the board a terminal of `size` would have.
+    pub fn new(size: (u16, u16)) -> Env {
+        let mut game_state = create_game_state(0, size);
+        let board = shown_board(&mut game_state);
+        let mut env = Env {
+            size,
+            rewards: Rewards::default(),
+            end_on_death: false,
+            game_state,
+            tiles: tiles(&board),
+            board,
+            observation: Vec::new(),
+            last_meal: 0
+        };
+        env.observe();
+        env
+    }
+
+    pub fn rewards(mut self, rewards: Rewards) -> Env {
+        self.rewards = rewards;
+        self
+    }
+
+    /// Ends episodes at the first mistake rather than once all lives are lost.
+    pub fn end_on_death(mut self, end_on_death: bool) -> Env {
+        self.end_on_death = end_on_death;
+        self
+    }
+
+    /// The size of an observation: channels, rows and columns.
+    pub fn shape(&self) -> (usize, usize, usize) {
+        (CHANNELS, self.board.rows.len(), self.board.width)
+    }
+
+    /// Starts a new episode, deterministic for `seed`, and returns its first
+    /// observation.
+    pub fn reset(&mut self, seed: u64) -> &[u8] {
+        self.game_state = create_game_state(seed, self.size);
+        self.last_meal = 0;
+        self.observe();
+        &self.observation
+    }
+
+    /// Turns towards `action`, unless that's back, and moves on by one tick.
+    /// A step after a mistake or a finished level gets going again first, and
+    /// one after the game is lost starts a new episode, seeded by the last.
+    pub fn step(&mut self, action: Direction) -> (&[u8], f32, bool) {
+        if game_over(&self.game_state) {
+            // restarting would seed the new game from the system instead
+            let seed = self.game_state.rng.gen();
+            self.reset(seed);
+        }
+        let game_state = &mut self.game_state;
+        if game_state.paused {
+            advance(GameEvent::Action, game_state);
+        }
+        let (score, lives, level) = (game_state.score, game_state.lives, game_state.level);
+        let distance = food_distance(game_state);
+
+        advance(turn(action), game_state);
+        advance(GameEvent::Timeout, game_state);
+
+        let mut reward = self.rewards.step;
+        if game_state.lives < lives {
+            reward += self.rewards.death;
+        } else if game_state.level != level {
+            reward += self.rewards.level;
+        } else if game_state.score > score {
+            reward += self.rewards.food;
+        } else {
+            reward += self.rewards.approach * (distance - food_distance(game_state)) as f32;
+        }
+        if game_state.score > score {
+            self.last_meal = game_state.ticks;
+        }
+
+        let done = game_over(game_state)
+            || (self.end_on_death && game_state.lives < lives)
+            || game_state.ticks - self.last_meal >= STARVATION_STEPS;
+        self.observe();
+        (&self.observation, reward, done)
+    }
+
+    fn observe(&mut self) {
+        let board = shown_board(&mut self.game_state);
+        if !Rc::ptr_eq(&board, &self.board) {
+            self.tiles = tiles(&board);
+            self.board = board;
+        }
+
+        let plane = self.tiles.len();
+        let channel = |channel: usize| channel * plane..(channel + 1) * plane;
+        self.observation.resize(CHANNELS * plane, 0);
+        self.observation[channel(TILES)].copy_from_slice(&self.tiles);
+
+        let width = self.board.width;
+        let snake = &mut self.observation[channel(SNAKE)];
+        snake.fill(0);
+        // the snake is always on the board while the game runs
+        for (index, &SnakeCell(x, y)) in self.game_state.snake.iter().enumerate() {
+            snake[y as usize * width + x as usize] = if index == 0 { 2 } else { 1 };
+        }
+
+        let food = &mut self.observation[channel(FOOD)];
+        food.fill(0);
+        let (x, y) = self.game_state.number_pos;
+        let top = y - y % 2;
+        food[top * width + x] = self.game_state.current_number;
+        food[(top + 1) * width + x] = self.game_state.current_number;
+    }
+}
+
+fn tiles(board: &Board) -> Vec<u8> {
+    let mut tiles = Vec::with_capacity(board.rows.len() * board.width);
+    for (y, row) in board.rows.iter().enumerate() {
+        tiles.extend(row.iter().enumerate().map(|(x, &pixel)| (x < 1 || y < 1 || pixel >= 0) as u8));
+    }
+    tiles
+}
+
+fn turn(direction: Direction) -> GameEvent {
+    match direction {
+        Direction::Up => GameEvent::Up,
+        Direction::Right => GameEvent::Right,
+        Direction::Down => GameEvent::Down,
+        Direction::Left => GameEvent::Left
+    }
+}
+
+/// How many moves the head is away from the number, not counting walls.
+fn food_distance(game_state: &GameState) -> i32 {
+    if game_state.snake.is_empty() {
+        return 0;
+    }
+    let head = game_state.snake.head();
+    let (x, y) = game_state.number_pos;
+    let top = (y - y % 2) as i32;
+    (head.0 - x as i32).abs() + (head.1 - top).abs().min((head.1 - top - 1).abs())
+}
+
+/// Several environments stepped together, with observations, rewards and
+/// ends of episodes laid out back to back. An environment whose episode ended
+/// starts the next one right away, seeded from the seed the batch was reset
+/// with, and reports that episode's first observation.
+pub struct VecEnv {
+    envs: Vec<Env>,
+    seeds: GameRng,
+    observations: Vec<u8>,
+    rewards: Vec<f32>,
+    dones: Vec<bool>
+}
+
+impl VecEnv {
+    pub fn new(envs: Vec<Env>) -> VecEnv {
+        let count = envs.len();
+        VecEnv {
+            envs,
+            seeds: GameRng::seed_from_u64(0),
+            observations: Vec::new(),
+            rewards: vec![0.0; count],
+            dones: vec![false; count]
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.envs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.envs.is_empty()
+    }
+
+    /// The size of one environment's observation, which has to be the same
+    /// for all of them.
+    pub fn shape(&self) -> (usize, usize, usize) {
+        self.envs[0].shape()
+    }
+
+    pub fn reset(&mut self, seed: u64) -> &[u8] {
+        self.seeds = GameRng::seed_from_u64(seed);
+        self.observations.clear();
+        for env in self.envs.iter_mut() {
+            self.observations.extend_from_slice(env.reset(self.seeds.gen()));
+        }
+        &self.observations
+    }
+
+    /// Steps every environment with the action at its index.
+    pub fn step(&mut self, actions: &[Direction]) -> (&[u8], &[f32], &[bool]) {
+        assert_eq!(actions.len(), self.envs.len());
+        self.observations.clear();
+        for (index, (env, &action)) in self.envs.iter_mut().zip(actions).enumerate() {
+            let (observation, reward, done) = env.step(action);
+            self.rewards[index] = reward;
+            self.dones[index] = done;
+            let observation = if done { env.reset(self.seeds.gen()) } else { observation };
+            self.observations.extend_from_slice(observation);
+        }
+        (&self.observations, &self.rewards, &self.dones)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACTIONS: [Direction; 4] = [Direction::Up, Direction::Right, Direction::Down, Direction::Left];
+
+    fn play(env: &mut Env, seed: u64, steps: usize) -> Vec<(Vec<u8>, f32, bool)> {
+        let mut rng = GameRng::seed_from_u64(seed);
+        env.reset(seed);
+        (0..steps).map(|_| {
+            let (observation, reward, done) = env.step(ACTIONS[rng.gen_range(0..4)]);
+            (observation.to_vec(), reward, done)
+        }).collect()
+    }
+
+    #[test]
+    fn episodes_are_deterministic_per_seed() {
+        let mut env = Env::new((40, 16));
+        assert_eq!(play(&mut env, 7, 300), play(&mut env, 7, 300));
+        assert_ne!(play(&mut env, 7, 300), play(&mut env, 8, 300));
+    }
+
+    #[test]
+    fn observations_show_the_board_snake_and_food() {
+        let mut env = Env::new((40, 16));
+        let (channels, rows, columns) = env.shape();
+        let observation = env.reset(3).to_vec();
+        assert_eq!(observation.len(), channels * rows * columns);
+
+        let plane = |channel: usize| &observation[channel * rows * columns..(channel + 1) * rows * columns];
+        let game_state = &env.game_state;
+        let at = |&SnakeCell(x, y): &SnakeCell| y as usize * columns + x as usize;
+        assert_eq!(plane(TILES)[0], 1, "out of bounds should count as a wall");
+        assert_eq!(plane(SNAKE)[at(&game_state.snake.head())], 2);
+        assert_eq!(plane(SNAKE).iter().filter(|&&pixel| pixel == 2).count(), 1);
+        assert_eq!(plane(FOOD).iter().filter(|&&pixel| pixel == 1).count(), 2);
+        for cell in game_state.snake.iter() {
+            assert_eq!(plane(TILES)[at(cell)], 0, "the snake sits on a free pixel");
+        }
+    }
+
+    #[test]
+    fn running_into_the_border_ends_the_episode() {
+        let mut env = Env::new((40, 16)).end_on_death(true).rewards(Rewards { death: -10.0, ..Rewards::default() });
+        env.reset(1);
+        let heading = env.game_state.snake_direction;
+        // keep going the way it spawned until it hits the border
+        let (reward, done) = (0..100)
+            .map(|_| {
+                let (_, reward, done) = env.step(heading);
+                (reward, done)
+            })
+            .find(|&(_, done)| done)
+            .unwrap();
+        assert!(done);
+        assert_eq!(reward, -10.0);
+    }
+
+    #[test]
+    fn approaching_the_food_is_rewarded() {
+        let mut env = Env::new((40, 16)).rewards(Rewards { approach: 0.5, ..Rewards::default() });
+        env.reset(2);
+        let before = food_distance(&env.game_state);
+        let (_, reward, _) = env.step(env.game_state.snake_direction);
+        let after = food_distance(&env.game_state);
+        assert_eq!(reward, 0.5 * (before - after) as f32);
+    }
+
+    #[test]
+    fn finished_episodes_restart_in_place() {
+        let mut envs = VecEnv::new((0..3).map(|_| Env::new((40, 16)).end_on_death(true)).collect());
+        let size = envs.reset(5).len();
+        let mut episodes = 0;
+        for _ in 0..200 {
+            let (observations, _, dones) = envs.step(&[Direction::Up; 3]);
+            assert_eq!(observations.len(), size);
+            episodes += dones.iter().filter(|&&done| done).count();
+        }
+        // heading up runs into the border within a few dozen ticks
+        assert!(episodes >= 3 * 4, "{episodes} episodes");
+    }
+
+    #[test]
+    fn stepping_on_after_a_lost_game_stays_deterministic() {
+        let mut env = Env::new((40, 16));
+        let mut lost = || {
+            env.reset(4);
+            env.game_state.lives = 1;
+            let heading = env.game_state.snake_direction;
+            while !env.step(heading).2 {}
+            (0..50).map(|_| env.step(heading).0.to_vec()).collect::<Vec<_>>()
+        };
+        assert_eq!(lost(), lost());
+    }
+}
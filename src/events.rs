@@ -0,0 +1,515 @@
+use crossterm::event::{Event, poll, read};
+use mio::net::UnixStream;
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Registry, Token};
+use signal_hook::SigId;
+use signal_hook::consts::signal::{SIGCONT, SIGHUP, SIGTERM, SIGTSTP, SIGWINCH};
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use crate::{GameEvent, translate_event};
+
+const SIGNALS: Token = Token(0);
+const TICKER: Token = Token(1);
+/// Every peer gets a range of this many tokens, the first one starting at
+/// `PEER_TOKENS`, so it can register new sources (say, accepted connections)
+/// without asking the loop.
+const PEER_TOKENS: usize = 1 << 16;
+
+/// Something besides the tick the game waits on, like the terminal or a
+/// socket.
+pub(crate) trait Peer {
+    /// Registers the peer's sources with tokens from `base` on.
+    fn register(&mut self, registry: &Registry, base: usize) -> io::Result<()>;
+
+    /// Called when the source registered as `base + offset` is ready. Events
+    /// for the game go into `out`.
+    fn ready(&mut self, registry: &Registry, offset: usize, out: &mut Vec<GameEvent>) -> io::Result<()>;
+
+    /// Called with the number of ticks that are due, once the ready sources
+    /// were handled. Peers that run games of their own step them here.
+    fn tick(&mut self, _registry: &Registry, _ticks: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Waits on all of the game's inputs at once and turns wall-clock time into
+/// simulation ticks.
+///
+/// Ticks come from a fixed-timestep accumulator: every wakeup adds the time
+/// that passed to it and takes out as many whole steps as fit, so the number
+/// of ticks never drifts from the clock no matter when or how often we wake.
+pub(crate) struct EventLoop {
+    poll: Poll,
+    events: Events,
+    signals: SignalPipe,
+    ticker: Ticker,
+    peers: Vec<Box<dyn Peer>>,
+    step: Duration,
+    accumulator: Duration,
+    last: Instant
+}
+
+impl EventLoop {
+    /// Creates a loop ticking every `step`. It wakes on the signals handled by
+    /// `TerminalGuard`, so the caller gets to check them right away.
+    pub(crate) fn new(step: Duration) -> io::Result<EventLoop> {
+        let poll = Poll::new()?;
+        let mut signals = SignalPipe::new(&[SIGTERM, SIGHUP, SIGTSTP, SIGCONT])?;
+        poll.registry().register(&mut signals.reader, SIGNALS, Interest::READABLE)?;
+
+        let last = Instant::now();
+        let ticker = Ticker::new(step)?;
+        ticker.register(poll.registry(), TICKER)?;
+
+        Ok(EventLoop {
+            poll,
+            events: Events::with_capacity(64),
+            signals,
+            ticker,
+            peers: Vec::new(),
+            step,
+            accumulator: Duration::ZERO,
+            last
+        })
+    }
+
+    pub(crate) fn attach(&mut self, mut peer: Box<dyn Peer>) -> io::Result<()> {
+        let base = PEER_TOKENS * (self.peers.len() + 1);
+        peer.register(self.poll.registry(), base)?;
+        self.peers.push(peer);
+        Ok(())
+    }
+
+    /// Blocks until something happened or `deadline` passed, puts the events
+    /// the peers produced into `out` and returns how many ticks are due.
+    pub(crate) fn wait(&mut self, out: &mut Vec<GameEvent>, deadline: Option<Instant>) -> io::Result<u32> {
+        let until_tick = self.ticker.timeout(self.step - self.accumulator.min(self.step));
+        let until_deadline = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let timeout = match (until_tick, until_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        };
+        match self.poll.poll(&mut self.events, timeout) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            result => result?
+        }
+
+        for event in self.events.iter() {
+            match event.token() {
+                SIGNALS => self.signals.drain()?,
+                TICKER => self.ticker.drain()?,
+                Token(token) => {
+                    let (peer, offset) = (token / PEER_TOKENS - 1, token % PEER_TOKENS);
+                    self.peers[peer].ready(self.poll.registry(), offset, out)?;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        self.accumulator += now - self.last;
+        self.last = now;
+        let mut ticks = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            ticks += 1;
+        }
+        if ticks > 0 {
+            for peer in self.peers.iter_mut() {
+                peer.tick(self.poll.registry(), ticks)?;
+            }
+        }
+        Ok(ticks)
+    }
+
+    /// Starts counting time afresh, for when we were stopped and shouldn't
+    /// catch up on the ticks missed meanwhile.
+    pub(crate) fn reset_clock(&mut self) -> io::Result<()> {
+        self.accumulator = Duration::ZERO;
+        self.last = Instant::now();
+        self.ticker.start(self.step)
+    }
+}
+
+/// A pipe written to by signal handlers, so a signal wakes up `Poll`.
+struct SignalPipe {
+    reader: UnixStream,
+    /// Each handler owns a copy of the write end, closed when it's
+    /// unregistered.
+    ids: Vec<SigId>
+}
+
+impl SignalPipe {
+    fn new(signals: &[i32]) -> io::Result<SignalPipe> {
+        let (reader, writer) = std::os::unix::net::UnixStream::pair()?;
+        reader.set_nonblocking(true)?;
+        writer.set_nonblocking(true)?;
+        let ids = signals.iter()
+            .map(|&signal| signal_hook::low_level::pipe::register(signal, writer.try_clone()?))
+            .collect::<io::Result<_>>()?;
+        Ok(SignalPipe { reader: UnixStream::from_std(reader), ids })
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        drain(&mut self.reader)
+    }
+}
+
+impl Drop for SignalPipe {
+    fn drop(&mut self) {
+        for &id in self.ids.iter() {
+            signal_hook::low_level::unregister(id);
+        }
+    }
+}
+
+fn drain(source: &mut impl Read) -> io::Result<()> {
+    let mut buffer = [0; 64];
+    loop {
+        match source.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(_) => continue,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        }
+    }
+}
+
+/// Accepts connections with `accept` until none are waiting, handing each
+/// to `admit`. Any other failure ends the round, and is most likely running
+/// out of file descriptors, which may pass.
+pub(crate) fn accept_all<S>(mut accept: impl FnMut() -> io::Result<S>, mut admit: impl FnMut(S)) -> io::Result<()> {
+    loop {
+        match accept() {
+            Ok(stream) => admit(stream),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        }
+    }
+}
+
+/// Reads everything a non-blocking `source` has into `input`. Says whether
+/// it's still open.
+pub(crate) fn receive(source: &mut impl Read, input: &mut Vec<u8>) -> io::Result<bool> {
+    let mut buffer = [0; 1024];
+    loop {
+        match source.read(&mut buffer) {
+            Ok(0) => return Ok(false),
+            Ok(read) => input.extend_from_slice(&buffer[..read]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        }
+    }
+}
+
+/// Writes as much of `backlog` as a non-blocking `sink` takes, keeping the
+/// rest for when it's writable again.
+pub(crate) fn send(sink: &mut impl Write, backlog: &mut Vec<u8>) -> io::Result<()> {
+    while !backlog.is_empty() {
+        match sink.write(backlog) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => {
+                backlog.drain(..written);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(())
+}
+
+/// A peer's connections, by token offset minus one for its listener. Slots
+/// are reused once a connection is gone.
+pub(crate) struct Slots<T> {
+    slots: Vec<Option<T>>
+}
+
+impl<T> Slots<T> {
+    pub(crate) fn new() -> Slots<T> {
+        Slots { slots: Vec::new() }
+    }
+
+    /// The slot the next connection goes in.
+    pub(crate) fn vacant(&self) -> usize {
+        self.slots.iter().position(Option::is_none).unwrap_or(self.slots.len())
+    }
+
+    pub(crate) fn insert(&mut self, index: usize, item: T) {
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, || None);
+        }
+        self.slots[index] = Some(item);
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.slots.get_mut(index).and_then(Option::as_mut)
+    }
+
+    pub(crate) fn remove(&mut self, index: usize) -> Option<T> {
+        self.slots.get_mut(index).and_then(Option::take)
+    }
+
+    /// How many slots there are, taken or not.
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut().flatten()
+    }
+
+    /// Drops the connections `keep` says are done.
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&mut T) -> bool) {
+        for slot in self.slots.iter_mut() {
+            if slot.as_mut().is_some_and(|item| !keep(item)) {
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// Waits until `fd` has something to read, or `deadline` passed.
+pub(crate) fn wait_readable(fd: RawFd, deadline: Instant) -> io::Result<bool> {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut poll_fd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        // rounded up, or we'd spin through the last millisecond
+        let timeout = remaining.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+        match unsafe { libc::poll(&mut poll_fd, 1, timeout) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            ready => return Ok(ready > 0)
+        }
+    }
+}
+
+/// A periodic timer backed by a timerfd, so the kernel wakes us up on every
+/// step without us working out timeouts.
+#[cfg(target_os = "linux")]
+struct Ticker {
+    fd: File
+}
+
+#[cfg(target_os = "linux")]
+impl Ticker {
+    fn new(step: Duration) -> io::Result<Ticker> {
+        use std::os::fd::FromRawFd;
+
+        let fd = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ticker = Ticker { fd: unsafe { File::from_raw_fd(fd) } };
+        ticker.start(step)?;
+        Ok(ticker)
+    }
+
+    fn start(&self, step: Duration) -> io::Result<()> {
+        let interval = libc::timespec {
+            tv_sec: step.as_secs() as libc::time_t,
+            tv_nsec: step.subsec_nanos() as libc::c_long
+        };
+        let spec = libc::itimerspec { it_interval: interval, it_value: interval };
+        if unsafe { libc::timerfd_settime(self.fd.as_raw_fd(), 0, &spec, std::ptr::null_mut()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn register(&self, registry: &Registry, token: Token) -> io::Result<()> {
+        registry.register(&mut SourceFd(&self.fd.as_raw_fd()), token, Interest::READABLE)
+    }
+
+    /// Reads the expiration count. The accumulator does the counting, this
+    /// only rearms the fd.
+    fn drain(&mut self) -> io::Result<()> {
+        drain(&mut self.fd)
+    }
+
+    fn timeout(&self, _: Duration) -> Option<Duration> {
+        None
+    }
+}
+
+/// Without timerfd, the tick is the poll timeout until the next step is due.
+#[cfg(not(target_os = "linux"))]
+struct Ticker;
+
+#[cfg(not(target_os = "linux"))]
+impl Ticker {
+    fn new(_: Duration) -> io::Result<Ticker> {
+        Ok(Ticker)
+    }
+
+    fn start(&self, _: Duration) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn register(&self, _: &Registry, _: Token) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn timeout(&self, remaining: Duration) -> Option<Duration> {
+        Some(remaining)
+    }
+}
+
+/// Key presses and resizes from the terminal. Crossterm does the reading and
+/// parsing; we only wait until it has something, which is either input on the
+/// tty or a `SIGWINCH`.
+pub(crate) struct TerminalInput {
+    tty: Tty,
+    resized: SignalPipe,
+    keys: fn(Event) -> Option<GameEvent>
+}
+
+enum Tty {
+    Stdin,
+    /// stdin isn't a terminal, so crossterm reads from `/dev/tty`. Data there
+    /// makes every open description of it readable, this one included.
+    Opened(File)
+}
+
+impl TerminalInput {
+    pub(crate) fn new() -> io::Result<TerminalInput> {
+        let tty = if io::stdin().is_terminal() { Tty::Stdin } else { Tty::Opened(File::open("/dev/tty")?) };
+        let resized = SignalPipe::new(&[SIGWINCH])?;
+        // crossterm only starts listening for SIGWINCH itself on the first poll,
+        // and without that it has no resize to report when ours wakes us up
+        poll(Duration::ZERO)?;
+        Ok(TerminalInput { tty, resized, keys: translate_event })
+    }
+
+    /// Turns keys into events with `keys` rather than the usual way.
+    pub(crate) fn keys(mut self, keys: fn(Event) -> Option<GameEvent>) -> TerminalInput {
+        self.keys = keys;
+        self
+    }
+
+    fn fd(&self) -> RawFd {
+        match &self.tty {
+            Tty::Stdin => io::stdin().as_raw_fd(),
+            Tty::Opened(file) => file.as_raw_fd()
+        }
+    }
+}
+
+impl Peer for TerminalInput {
+    fn register(&mut self, registry: &Registry, base: usize) -> io::Result<()> {
+        registry.register(&mut SourceFd(&self.fd()), Token(base), Interest::READABLE)?;
+        registry.register(&mut self.resized.reader, Token(base + 1), Interest::READABLE)
+    }
+
+    fn ready(&mut self, _: &Registry, offset: usize, out: &mut Vec<GameEvent>) -> io::Result<()> {
+        if offset == 1 {
+            self.resized.drain()?;
+        }
+        while poll(Duration::ZERO)? {
+            out.extend((self.keys)(read()?));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mio::net::{TcpListener, TcpStream};
+    use std::io::Write;
+
+    use super::*;
+
+    /// Turns every byte a client sends into a direction key.
+    struct KeySocket {
+        base: usize,
+        listener: TcpListener,
+        clients: Vec<TcpStream>
+    }
+
+    impl Peer for KeySocket {
+        fn register(&mut self, registry: &Registry, base: usize) -> io::Result<()> {
+            self.base = base;
+            registry.register(&mut self.listener, Token(base), Interest::READABLE)
+        }
+
+        fn ready(&mut self, registry: &Registry, offset: usize, out: &mut Vec<GameEvent>) -> io::Result<()> {
+            if offset == 0 {
+                while let Ok((mut client, _)) = self.listener.accept() {
+                    let token = Token(self.base + self.clients.len() + 1);
+                    registry.register(&mut client, token, Interest::READABLE)?;
+                    self.clients.push(client);
+                }
+                return Ok(());
+            }
+            let mut buffer = [0; 16];
+            while let Ok(read @ 1..) = self.clients[offset - 1].read(&mut buffer) {
+                out.extend(buffer[..read].iter().map(|byte| match byte {
+                    b'w' => GameEvent::Up,
+                    b'a' => GameEvent::Left,
+                    b's' => GameEvent::Down,
+                    _ => GameEvent::Right
+                }));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ticks_keep_up_with_the_clock() {
+        let step = Duration::from_millis(20);
+        let mut event_loop = EventLoop::new(step).unwrap();
+        let start = Instant::now();
+        let mut ticks = 0;
+        while ticks < 10 {
+            ticks += event_loop.wait(&mut Vec::new(), None).unwrap();
+            // a slow frame mustn't cost us any ticks
+            if ticks == 3 {
+                std::thread::sleep(step * 3);
+            }
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= step * ticks, "{ticks} ticks after {elapsed:?}");
+        assert!(elapsed < step * (ticks + 3), "{ticks} ticks after {elapsed:?}");
+    }
+
+    #[test]
+    fn socket_peers_feed_events() {
+        let mut event_loop = EventLoop::new(Duration::from_secs(10)).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = listener.local_addr().unwrap();
+        event_loop.attach(Box::new(KeySocket { base: 0, listener, clients: Vec::new() })).unwrap();
+
+        let mut client = std::net::TcpStream::connect(address).unwrap();
+        client.write_all(b"wasd").unwrap();
+
+        let mut events = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while events.len() < 4 && Instant::now() < deadline {
+            assert_eq!(event_loop.wait(&mut events, None).unwrap(), 0);
+        }
+        assert!(matches!(events[..], [GameEvent::Up, GameEvent::Left, GameEvent::Down, GameEvent::Right]));
+    }
+
+    #[test]
+    fn slots_of_gone_connections_are_reused() {
+        let mut slots = Slots::new();
+        for index in 0..3 {
+            slots.insert(slots.vacant(), index);
+        }
+        slots.retain(|&mut item| item != 1);
+        assert_eq!(slots.vacant(), 1);
+        assert_eq!(slots.remove(2), Some(2));
+        slots.insert(slots.vacant(), 3);
+        assert_eq!(slots.iter_mut().map(|item| *item).collect::<Vec<_>>(), [0, 3]);
+        assert_eq!((slots.len(), slots.vacant()), (3, 2));
+    }
+}
@@ -0,0 +1,184 @@
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen, enable_raw_mode, disable_raw_mode};
+use crossterm::{cursor, queue};
+use signal_hook::consts::signal::{SIGCONT, SIGHUP, SIGTERM, SIGTSTP};
+use signal_hook::low_level::emulate_default_handler;
+use std::fs::OpenOptions;
+use std::io::{stdout, Read, Write};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::events::wait_readable;
+
+/// How long a terminal gets to answer a query, for one that doesn't answer
+/// at all.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Whether the terminal is currently in raw mode on the alternate screen.
+/// Shared between the guard, the panic hook and the suspend path so that
+/// restoring twice is harmless.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Puts the terminal into game mode and puts it back when dropped, when the
+/// game panics, or when one of the handled signals asks us to leave.
+pub(crate) struct TerminalGuard {
+    signals: Signals
+}
+
+/// Flags raised asynchronously by signal handlers and polled by the event loop.
+#[derive(Default)]
+struct Signals {
+    terminate: Arc<AtomicBool>,
+    suspend: Arc<AtomicBool>,
+    resumed: Arc<AtomicBool>
+}
+
+/// What the event loop has to do after checking the signal flags.
+pub(crate) enum SignalAction {
+    Nothing,
+    Quit,
+    Resumed
+}
+
+impl TerminalGuard {
+    pub(crate) fn enter() -> std::io::Result<TerminalGuard> {
+        install_panic_hook();
+
+        let signals = Signals::default();
+        for signal in [SIGTERM, SIGHUP] {
+            signal_hook::flag::register(signal, Arc::clone(&signals.terminate))?;
+        }
+        signal_hook::flag::register(SIGTSTP, Arc::clone(&signals.suspend))?;
+        signal_hook::flag::register(SIGCONT, Arc::clone(&signals.resumed))?;
+
+        acquire()?;
+        Ok(TerminalGuard { signals })
+    }
+
+    /// Hands the terminal back to the shell and stops the process like the
+    /// default `SIGTSTP` action would. Returns once we have been continued,
+    /// with the terminal back in game mode.
+    pub(crate) fn suspend(&self) -> std::io::Result<()> {
+        restore();
+        emulate_default_handler(SIGTSTP)?;
+        self.signals.resumed.store(false, Ordering::SeqCst);
+        acquire()
+    }
+
+    pub(crate) fn check_signals(&self) -> std::io::Result<SignalAction> {
+        if self.signals.terminate.load(Ordering::SeqCst) {
+            return Ok(SignalAction::Quit);
+        }
+        if self.signals.suspend.swap(false, Ordering::SeqCst) {
+            self.suspend()?;
+            return Ok(SignalAction::Resumed);
+        }
+        // continued after an untrappable SIGSTOP; the shell may have reset the tty
+        if self.signals.resumed.swap(false, Ordering::SeqCst) {
+            ACTIVE.store(false, Ordering::SeqCst);
+            acquire()?;
+            return Ok(SignalAction::Resumed);
+        }
+        Ok(SignalAction::Nothing)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+fn acquire() -> std::io::Result<()> {
+    if ACTIVE.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let mut stdout = stdout();
+    enable_raw_mode()?;
+    queue!(stdout, cursor::Hide, EnterAlternateScreen)?;
+    stdout.flush()
+}
+
+/// Leaves raw mode and the alternate screen. Errors are ignored, as this runs
+/// on the way out (possibly after `SIGHUP` took the tty away).
+pub(crate) fn restore() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let mut stdout = stdout();
+    let _ = queue!(stdout, cursor::Show, LeaveAlternateScreen);
+    let _ = stdout.flush();
+    let _ = disable_raw_mode();
+}
+
+/// Asks the terminal whether it knows synchronized output (`?2026`), with
+/// DECRQM. Every terminal answers the device attributes query that follows,
+/// so one that ignores DECRQM doesn't keep us waiting. Wants raw mode, or the
+/// answers are echoed.
+pub(crate) fn synchronized_output_supported() -> bool {
+    let Ok(mut tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+        return false;
+    };
+    if tty.write_all(b"\x1b[?2026$p\x1b[c").is_err() {
+        return false;
+    }
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut answers = Vec::new();
+    let mut buffer = [0; 64];
+    loop {
+        if let Some(supported) = synchronized_output_answer(&answers) {
+            return supported;
+        }
+        if !wait_readable(tty.as_raw_fd(), deadline).unwrap_or(false) {
+            return false;
+        }
+        match tty.read(&mut buffer) {
+            Ok(read @ 1..) => answers.extend_from_slice(&buffer[..read]),
+            _ => return false
+        }
+    }
+}
+
+/// What `answers` say about synchronized output, once the device attributes
+/// are among them. The mode is there if it's set or reset, and not if the
+/// terminal doesn't know it, it's stuck off, or there was no report at all.
+fn synchronized_output_answer(answers: &[u8]) -> Option<bool> {
+    let text = String::from_utf8_lossy(answers);
+    let mut reports = text.split('\x1b').filter_map(|report| report.strip_prefix("[?"));
+    let mut supported = false;
+    loop {
+        let report = reports.next()?;
+        if let Some(status) = report.strip_prefix("2026;").and_then(|rest| rest.strip_suffix("$y")) {
+            supported = matches!(status, "1" | "2" | "3");
+        } else if report.ends_with('c') {
+            return Some(supported);
+        }
+    }
+}
+
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // restore first, otherwise the message ends up on the alternate screen
+        restore();
+        default_hook(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synchronized_output_is_read_from_the_mode_report() {
+        assert_eq!(synchronized_output_answer(b"\x1b[?2026;2$y\x1b[?62;22c"), Some(true));
+        assert_eq!(synchronized_output_answer(b"\x1b[?2026;1$y\x1b[?1;2c"), Some(true));
+        assert_eq!(synchronized_output_answer(b"\x1b[?2026;0$y\x1b[?62;22c"), Some(false));
+        assert_eq!(synchronized_output_answer(b"\x1b[?2026;4$y\x1b[?62;22c"), Some(false));
+        // no DECRQM, just the device attributes
+        assert_eq!(synchronized_output_answer(b"\x1b[?1;2c"), Some(false));
+        assert_eq!(synchronized_output_answer(b"\x1b[?2026;2$y"), None);
+        assert_eq!(synchronized_output_answer(b"\x1b[?2026;2"), None);
+    }
+}
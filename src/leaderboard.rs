@@ -0,0 +1,500 @@
+//! A leaderboard for tournament nights, run with `--leaderboard <port>`.
+//! Scores only get in together with the replay of their game, which is
+//! played back headlessly to check that it ends the way the submission says.
+//! Rankings are kept per level set, game mode and difficulty.
+//!
+//! Every connection sends one request as a line of JSON and gets one line
+//! back:
+//!
+//! ```text
+//! > {"command":"submit","name":"ann","score":5300,"level":2,
+//!    "replay":{"seed":..,"ticks":..,"opponents":0,"difficulty":"normal","input_log":[..]}}
+//! < {"ok":true,"category":"classic, campaign, solo","place":3,"rankings":[{"name":"bob","score":9100,"level":3},..]}
+//! > {"command":"rankings","levels":"classic","mode":"zen","difficulty":"hard"}
+//! < {"ok":true,"category":"classic, zen, hard","rankings":[..]}
+//! ```
+//!
+//! Games without opponents have no difficulty, ask for theirs without one.
+//! Without a mode, it's the campaign.
+//! Replays that made it in are kept next to the rankings, which is also how
+//! the same game is turned away the second time.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry as Slot;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::battle::{Difficulty, MAX_OPPONENTS};
+use crate::crash::Replay;
+use crate::data::data_dir;
+use crate::modes::Mode;
+use crate::scores::{self, Entry, HighScores};
+use crate::{GameEvent, GameState, advance, game_over};
+
+/// The level sets there are rankings for, the usual ones and the same levels
+/// played as light-cycle arenas.
+const LEVEL_SETS: &[&str] = &["classic", "light-cycle"];
+/// Terminal sizes a replay may be played at, the ones `--serve` allows.
+pub(crate) const MIN_SIZE: (u16, u16) = (40, 16);
+pub(crate) const MAX_SIZE: (u16, u16) = (500, 200);
+/// The longest game we play back, about a day at ten ticks a second.
+const MAX_TICKS: u64 = 1_000_000;
+/// The longest request we read.
+const MAX_REQUEST: u64 = 16 << 20;
+/// The longest name we keep.
+const MAX_NAME: usize = 24;
+/// How many connections are served at once. Anyone past that is turned away.
+const MAX_CONNECTIONS: usize = 64;
+/// How long either side waits for the other.
+const TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Which ranking a game goes into.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Category {
+    levels: &'static str,
+    mode: Mode,
+    /// The opponents', `None` for games without.
+    difficulty: Option<Difficulty>
+}
+
+impl Category {
+    fn of(replay: &Replay) -> Category {
+        let levels = if replay.light_cycle { LEVEL_SETS[1] } else { LEVEL_SETS[0] };
+        Category { levels, mode: replay.mode, difficulty: (replay.opponents > 0).then_some(replay.difficulty) }
+    }
+
+    fn file_name(&self) -> String {
+        match self.difficulty {
+            Some(difficulty) => format!("{}-{}-{difficulty}.json", self.levels, self.mode),
+            None => format!("{}-{}-solo.json", self.levels, self.mode)
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.difficulty {
+            Some(difficulty) => write!(f, "{}, {}, {difficulty}", self.levels, self.mode),
+            None => write!(f, "{}, {}, solo", self.levels, self.mode)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Request {
+    Submit { name: String, score: i32, level: u8, replay: Replay },
+    Rankings {
+        levels: String,
+        #[serde(default)]
+        mode: Mode,
+        #[serde(default)]
+        difficulty: Option<Difficulty>
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Reply {
+    ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    /// Where a submitted score ended up, counting from 1, if it made it in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    place: Option<usize>,
+    #[serde(default)]
+    rankings: Vec<Entry>
+}
+
+impl Reply {
+    fn error(error: impl Into<String>) -> Reply {
+        Reply { error: Some(error.into()), ..Reply::default() }
+    }
+}
+
+/// Serves the leaderboard on `port` on every interface until killed, every
+/// connection on a thread of its own. Port 0 picks a free one; either way the
+/// address goes to stdout first thing.
+pub(crate) fn run(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port)))?;
+    let dir = data_dir()?.join("leaderboard");
+    fs::create_dir_all(dir.join("replays"))?;
+    let mut stdout = io::stdout();
+    writeln!(stdout, "listening on {}", listener.local_addr()?)?;
+    stdout.flush()?;
+
+    listen(listener, Leaderboard { dir, tables: HashMap::new() });
+    Ok(())
+}
+
+/// Takes connections on `listener` for good.
+fn listen(listener: TcpListener, leaderboard: Leaderboard) {
+    let leaderboard = Arc::new(Mutex::new(leaderboard));
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("accepting failed: {err}");
+                continue;
+            }
+        };
+        if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::Relaxed);
+            let _ = reply(&mut stream, &Reply::error("too busy, try again later"));
+            continue;
+        }
+        let (leaderboard, connections) = (Arc::clone(&leaderboard), Arc::clone(&connections));
+        thread::spawn(move || {
+            // a client that misbehaves or goes away is no reason to stop
+            if let Err(err) = serve(&leaderboard, stream) {
+                eprintln!("request failed: {err}");
+            }
+            connections.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+fn serve(leaderboard: &Mutex<Leaderboard>, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new((&stream).take(MAX_REQUEST)).read_line(&mut line)?;
+    let answer = match serde_json::from_str(&line) {
+        Ok(request) => handle(leaderboard, request).unwrap_or_else(Reply::error),
+        Err(err) => Reply::error(format!("not a request: {err}"))
+    };
+    reply(&mut stream, &answer)
+}
+
+fn reply(stream: &mut TcpStream, reply: &Reply) -> io::Result<()> {
+    serde_json::to_writer(&mut *stream, reply)?;
+    stream.write_all(b"\n")
+}
+
+fn handle(leaderboard: &Mutex<Leaderboard>, request: Request) -> Result<Reply, String> {
+    match request {
+        Request::Submit { name, score, level, replay } => {
+            leaderboard.lock().unwrap().check_new(&replay)?;
+            // playing the game back takes a while, the others don't wait for it
+            let game_state = verify(&replay, score, level)?;
+            leaderboard.lock().unwrap().submit(&name, score, level, &replay, game_state.splits)
+        }
+        Request::Rankings { levels, mode, difficulty } => {
+            let levels = LEVEL_SETS.iter().find(|&&name| name == levels).ok_or("no such level set")?;
+            let category = Category { levels, mode, difficulty };
+            let table = leaderboard.lock().unwrap().table(&category)?.entries().to_vec();
+            Ok(Reply { ok: true, category: Some(category.to_string()), rankings: table, ..Reply::default() })
+        }
+    }
+}
+
+struct Leaderboard {
+    dir: PathBuf,
+    /// The rankings asked for so far, loaded on first use.
+    tables: HashMap<Category, HighScores>
+}
+
+impl Leaderboard {
+    fn replay_path(&self, replay: &Replay) -> PathBuf {
+        self.dir.join("replays").join(format!("{:016x}.json", replay.seed))
+    }
+
+    /// Turns away a game that made it in before.
+    fn check_new(&self, replay: &Replay) -> Result<(), String> {
+        match self.replay_path(replay).exists() {
+            true => Err("this game was submitted already".to_string()),
+            false => Ok(())
+        }
+    }
+
+    /// Ranks a verified game, unless the same one got in while it was
+    /// being played back.
+    fn submit(&mut self, name: &str, score: i32, level: u8, replay: &Replay, splits: Vec<u64>) -> Result<Reply, String> {
+        self.check_new(replay)?;
+        let path = self.replay_path(replay);
+        let category = Category::of(replay);
+        let name: String = name.chars().filter(|c| !c.is_control()).take(MAX_NAME).collect();
+        let name = if name.trim().is_empty() { "anonymous".to_string() } else { name };
+        let table = self.table(&category)?;
+        let place = table.record(Entry { name, score, level, splits }).map_err(|err| err.to_string())?;
+        let rankings = table.entries().to_vec();
+        let save = || -> io::Result<()> {
+            serde_json::to_writer(BufWriter::new(File::create(&path)?), replay)?;
+            Ok(())
+        };
+        save().map_err(|err| err.to_string())?;
+
+        Ok(Reply {
+            ok: true,
+            category: Some(category.to_string()),
+            place: place.map(|place| place + 1),
+            rankings,
+            ..Reply::default()
+        })
+    }
+
+    fn table(&mut self, category: &Category) -> Result<&mut HighScores, String> {
+        match self.tables.entry(category.clone()) {
+            Slot::Occupied(slot) => Ok(slot.into_mut()),
+            Slot::Vacant(slot) => {
+                let table = HighScores::load(self.dir.join(category.file_name())).map_err(|err| err.to_string())?;
+                Ok(slot.insert(table))
+            }
+        }
+    }
+}
+
+/// Plays `replay` back and checks that the game ends with `score` on `level`.
+/// Returns the game as it ended.
+fn verify(replay: &Replay, score: i32, level: u8) -> Result<GameState, String> {
+    if replay.ticks > MAX_TICKS {
+        return Err("the game is too long".to_string());
+    }
+    if replay.opponents > MAX_OPPONENTS {
+        return Err("the game has too many opponents".to_string());
+    }
+    if replay.rival {
+        return Err("games against a rival player aren't ranked".to_string());
+    }
+    if replay.level.is_some() {
+        return Err("games on level files aren't ranked".to_string());
+    }
+    let mut last = 0;
+    for record in replay.input_log.iter() {
+        if record.tick < last || record.tick > replay.ticks {
+            return Err("the input log is out of order".to_string());
+        }
+        last = record.tick;
+        if let GameEvent::Resize(width, height) = record.event {
+            if !(MIN_SIZE.0..=MAX_SIZE.0).contains(&width) || !(MIN_SIZE.1..=MAX_SIZE.1).contains(&height) {
+                return Err("the terminal size is out of bounds".to_string());
+            }
+        }
+    }
+
+    // a replay is made up by whoever sends it, so the game's own checks
+    // may well trip over one
+    let played = panic::catch_unwind(AssertUnwindSafe(|| replay.run(|event, game_state| {
+        advance(event, game_state);
+    })));
+    let game_state = match played {
+        Ok(Ok(game_state)) => game_state,
+        Ok(Err(err)) => return Err(err.to_string()),
+        Err(_) => return Err("the replay doesn't play back".to_string())
+    };
+    if !game_over(&game_state) {
+        return Err("the game isn't over".to_string());
+    }
+    if (game_state.score, game_state.level) != (score, level) {
+        return Err(format!("the replay ends with {} points on level {}", game_state.score, game_state.level));
+    }
+    Ok(game_state)
+}
+
+/// Submits finished games to a leaderboard, in the background so that the
+/// game goes on while the server plays them back.
+pub(crate) struct Client {
+    address: String,
+    name: String,
+    /// Where the submission under way reports back.
+    pending: Option<Receiver<Result<Standing, String>>>
+}
+
+/// How a submission went, for the game over screen.
+pub(crate) struct Standing {
+    pub(crate) summary: String,
+    pub(crate) rankings: Vec<String>
+}
+
+impl Client {
+    /// A client for the leaderboard at `address`, as `host:port`, submitting
+    /// under `name`.
+    pub(crate) fn new(address: String, name: String) -> Client {
+        Client { address, name, pending: None }
+    }
+
+    /// Starts submitting the game in `game_state`, see `poll`. Does nothing
+    /// while another submission is under way.
+    pub(crate) fn submit(&mut self, game_state: &GameState) {
+        if self.pending.is_some() {
+            return;
+        }
+        let request = Request::Submit {
+            name: self.name.clone(),
+            score: game_state.score,
+            level: game_state.level,
+            replay: Replay::of(game_state)
+        };
+        let address = self.address.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // nobody is waiting for it anymore if this fails
+            let _ = sender.send(submit(&address, &request));
+        });
+        self.pending = Some(receiver);
+    }
+
+    /// Whether a submission is under way.
+    pub(crate) fn is_busy(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// How the submission went, once it's done.
+    pub(crate) fn poll(&mut self) -> Option<Result<Standing, String>> {
+        let result = match self.pending.as_ref()?.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err("the submission went missing".to_string())
+        };
+        self.pending = None;
+        Some(result)
+    }
+
+    /// Forgets about the submission under way, if there is one.
+    pub(crate) fn cancel(&mut self) {
+        self.pending = None;
+    }
+}
+
+fn submit(address: &str, request: &Request) -> Result<Standing, String> {
+    let reply = send(address, request).map_err(|err| err.to_string())?;
+    if !reply.ok {
+        return Err(reply.error.unwrap_or_default());
+    }
+    let category = reply.category.unwrap_or_default();
+    let summary = match reply.place {
+        Some(place) => format!("Submitted, number {place} in {category}"),
+        None => format!("Submitted, but not among the best in {category}")
+    };
+    Ok(Standing { summary, rankings: scores::lines(&reply.rankings) })
+}
+
+fn send(address: &str, request: &Request) -> io::Result<Reply> {
+    let address = address.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for the leaderboard"))?;
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    serde_json::to_writer(&mut stream, request)?;
+    stream.write_all(b"\n")?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::{GameRng, create_game_state, procedural};
+
+    /// A game played to the end by turning at random.
+    fn finished_game(seed: u64) -> GameState {
+        play_to_the_end(create_game_state(seed, (80, 24)))
+    }
+
+    fn play_to_the_end(mut game_state: GameState) -> GameState {
+        let seed = game_state.seed;
+        let mut rng = GameRng::seed_from_u64(seed);
+        while !game_over(&game_state) {
+            if game_state.paused {
+                advance(GameEvent::Action, &mut game_state);
+            }
+            if rng.gen_ratio(1, 6) {
+                let turn = [GameEvent::Up, GameEvent::Right, GameEvent::Down, GameEvent::Left][rng.gen_range(0..4)];
+                advance(turn, &mut game_state);
+            }
+            advance(GameEvent::Timeout, &mut game_state);
+        }
+        game_state
+    }
+
+    #[test]
+    fn replays_have_to_back_the_score() {
+        let game_state = finished_game(3);
+        let replay = Replay::of(&game_state);
+        assert!(verify(&replay, game_state.score, game_state.level).is_ok());
+        assert!(verify(&replay, game_state.score + 100, game_state.level).is_err());
+
+        let mut unfinished = replay.clone();
+        unfinished.input_log.retain(|record| !matches!(record.event, GameEvent::Action) || record.tick < 50);
+        assert_eq!(verify(&unfinished, game_state.score, game_state.level).err().unwrap(), "the game isn't over");
+
+        let mut tiny = replay;
+        tiny.input_log[0].event = GameEvent::Resize(4, 4);
+        assert!(verify(&tiny, game_state.score, game_state.level).is_err());
+    }
+
+    #[test]
+    fn games_on_level_files_replay_but_arent_ranked() {
+        let mut board = crate::empty_board((80, 24));
+        procedural::generate(procedural::Algorithm::Caves, &mut board, 2, 0.3);
+        let mut game_state = create_game_state(6, (80, 24));
+        game_state.level_file = Some(std::rc::Rc::new(board));
+        let game_state = play_to_the_end(game_state);
+
+        let replay = Replay::of(&game_state);
+        let replayed = replay.run(|event, game_state| {
+            advance(event, game_state);
+        }).unwrap();
+        assert_eq!((replayed.score, replayed.ticks), (game_state.score, game_state.ticks));
+        assert_eq!(verify(&replay, game_state.score, game_state.level).err().unwrap(), "games on level files aren't ranked");
+    }
+
+    /// Waits for the submission under way to come back.
+    fn wait(client: &mut Client) -> Result<Standing, String> {
+        loop {
+            if let Some(result) = client.poll() {
+                return result;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn submissions_are_ranked_once_over_the_network() {
+        let dir = std::env::temp_dir().join(format!("nibbles-leaderboard-{}", std::process::id()));
+        fs::create_dir_all(dir.join("replays")).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let leaderboard = Leaderboard { dir: dir.clone(), tables: HashMap::new() };
+        thread::spawn(move || listen(listener, leaderboard));
+
+        // a client that never says anything holds up nobody else
+        let _idle = TcpStream::connect(&address).unwrap();
+
+        let mut client = Client::new(address.clone(), "ann\x1b[2J".to_string());
+        let game_state = finished_game(5);
+        let started = std::time::Instant::now();
+        client.submit(&game_state);
+        assert!(client.is_busy());
+        let standing = wait(&mut client).unwrap();
+        assert!(started.elapsed() < TIMEOUT / 2, "the idle client held up the submission");
+        assert!(!client.is_busy());
+        assert_eq!(standing.summary, "Submitted, number 1 in classic, campaign, solo");
+        assert!(standing.rankings[0].ends_with("  ann[2J"), "{:?}", standing.rankings);
+        client.submit(&game_state);
+        assert_eq!(wait(&mut client).err().unwrap(), "this game was submitted already");
+
+        let reply = send(&address, &Request::Rankings { levels: "classic".to_string(), mode: Mode::Campaign, difficulty: None }).unwrap();
+        assert_eq!(reply.rankings.len(), 1);
+        assert_eq!(reply.rankings[0].score, game_state.score);
+        let reply = send(&address, &Request::Rankings { levels: "../etc".to_string(), mode: Mode::Campaign, difficulty: None }).unwrap();
+        assert!(!reply.ok);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,21 +1,20 @@
 use rand::{Rng, seq::SliceRandom};
 
-use crate::{Board, SnakeCell, Direction};
+use crate::{Board, SnakeCell, Direction, GameRng};
 
 pub(crate) fn render_l1(_: &mut Board) {}
 
-pub(crate) fn spawn_l1(board: &Board) -> (Vec<SnakeCell>, Direction) {
+pub(crate) fn spawn_l1(board: &Board, rng: &mut GameRng) -> (Vec<SnakeCell>, Direction) {
     let Board { width, height, .. } = *board;
     let center_x = width / 2;
     let center_y = height / 2;
 
-    let mut thread_random = rand::thread_rng();
     let head = SnakeCell(
-        thread_random.gen_range(center_x - 3 .. center_x + 3) as i32,
-        thread_random.gen_range(center_y - 3 .. center_y + 3) as i32
+        rng.gen_range(center_x - 3 .. center_x + 3) as i32,
+        rng.gen_range(center_y - 3 .. center_y + 3) as i32
     );
     let mut vec = vec![head];
-    let direction = thread_random.gen();
+    let direction = rng.gen();
     let SnakeCell(mut nx, mut ny) = head;
     match direction {
         Direction::Up | Direction::Down => ny -= direction.as_integer(),
@@ -36,20 +35,19 @@ pub(crate) fn render_l2(board: &mut Board) {
     draw_line(board, bar_x, bar_y, bar_x + bar_width, bar_y);
 }
 
-pub(crate) fn spawn_l2(board: &Board) -> (Vec<SnakeCell>, Direction) {
+pub(crate) fn spawn_l2(board: &Board, rng: &mut GameRng) -> (Vec<SnakeCell>, Direction) {
     let Board { width, height, .. } = *board;
 
-    let mut thread_random = rand::thread_rng();
     let mut head = SnakeCell(
-        thread_random.gen_range(1..width) as i32,
-        thread_random.gen_range(1..height) as i32
+        rng.gen_range(1..width) as i32,
+        rng.gen_range(1..height) as i32
     );
 
     if head.1 == height as i32 / 2 {
         head.1 -= 1;
     }
     let mut vec = vec![head];
-    let direction = *[Direction::Left, Direction::Right].choose(&mut thread_random).unwrap();
+    let direction = *[Direction::Left, Direction::Right].choose(rng).unwrap();
     let mut nx = head.0;
     nx -= direction.as_integer();
     vec.push(SnakeCell(nx, head.1));
@@ -70,20 +68,19 @@ pub(crate) fn render_l3(board: &mut Board) {
     draw_line(board, bar_x, bar_y, bar_x, bar_y + bar_height);
 }
 
-pub(crate) fn spawn_l3(board: &Board) -> (Vec<SnakeCell>, Direction) {
+pub(crate) fn spawn_l3(board: &Board, rng: &mut GameRng) -> (Vec<SnakeCell>, Direction) {
     let Board { width, height, .. } = *board;
 
-    let mut thread_random = rand::thread_rng();
     let mut head = SnakeCell(
-        thread_random.gen_range(1..width) as i32,
-        thread_random.gen_range(1..height) as i32
+        rng.gen_range(1..width) as i32,
+        rng.gen_range(1..height) as i32
     );
 
     if head.0 == width as i32 / 3 || head.0 == (width as i32 / 3) * 2 {
         head.0 -= 1;
     }
     let mut vec = vec![head];
-    let direction = *[Direction::Up, Direction::Down].choose(&mut thread_random).unwrap();
+    let direction = *[Direction::Up, Direction::Down].choose(rng).unwrap();
     let mut ny = head.1;
     ny -= direction.as_integer();
     vec.push(SnakeCell(head.0, ny));
@@ -120,17 +117,15 @@ pub(crate) fn render_l4(board: &mut Board) {
     draw_line(board, 1, bar_y, 1 + bar_width, bar_y);
 }
 
-pub(crate) fn spawn_centred(board: &Board) -> (Vec<SnakeCell>, Direction) {
+pub(crate) fn spawn_centred(board: &Board, rng: &mut GameRng) -> (Vec<SnakeCell>, Direction) {
     let Board { width, height, .. } = *board;
-
-    let mut thread_random = rand::thread_rng();
     let head = SnakeCell(
         width as i32 / 2,
         height as i32 / 2
     );
 
     let mut vec = vec![head];
-    let direction: Direction = thread_random.gen();
+    let direction: Direction = rng.gen();
     let SnakeCell(mut nx, mut ny) = head;
     match direction {
         Direction::Up | Direction::Down => ny -= direction.as_integer(),
@@ -257,7 +252,7 @@ fn draw_line_high(board: &mut Board, x0: isize, y0: isize, x1: isize, y1: isize)
     }
 }
 
-fn draw_line(board: &mut Board, x0: usize, y0: usize, x1: usize, y1: usize) {
+pub(crate) fn draw_line(board: &mut Board, x0: usize, y0: usize, x1: usize, y1: usize) {
     let (x0, y0) = (x0 as isize, y0 as isize);
     let (x1, y1) = (x1 as isize, y1 as isize);
 
@@ -0,0 +1,1201 @@
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+use backend::{Backend, CrosstermBackend};
+use events::{EventLoop, Peer, TerminalInput};
+use guard::{TerminalGuard, SignalAction};
+use leaderboard::MIN_SIZE;
+use levels::{render_l1, render_l2, render_l3, render_l4, render_l5, render_l6, render_l7, render_l8, spawn_l1, spawn_l2, spawn_l3, spawn_centred};
+use rand::{Rng, SeedableRng};
+use rand::distributions::{Distribution, Standard};
+use rand_chacha::ChaCha8Rng;
+use serde::{Serialize, Deserialize};
+use render::Renderer;
+use scores::{Entry, HighScores};
+use snake::Snake;
+/*use termion::event::Event;
+use termion::{
+    self, raw::IntoRawMode, screen::IntoAlternateScreen, input::TermRead, event::Key, cursor, color, clear};*/
+use std::cell::RefCell;
+use std::io::stdout;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+
+mod autopilot;
+mod backend;
+mod battle;
+mod bench;
+mod bot;
+mod control;
+mod crash;
+mod data;
+pub mod env;
+mod events;
+mod guard;
+mod leaderboard;
+mod levels;
+mod modes;
+mod procedural;
+mod race;
+mod render;
+mod scores;
+mod server;
+mod snake;
+#[cfg(test)]
+mod snapshot;
+mod spectate;
+mod telnet;
+mod terminal;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum GameEvent {
+    Timeout,
+    Up,
+    Right,
+    Down,
+    Left,
+    Action,
+    Escape,
+    Redraw,
+    Resize(u16, u16),
+    Suspend,
+    /// Sends the score of a lost game to the leaderboard.
+    Submit,
+    /// A key of the second player, in a race or as a rival.
+    Second(race::Key),
+    /// Switches to the next game mode on the title screen.
+    NextMode,
+    Quit
+}
+
+impl GameEvent {
+    fn map_to_direction_check_valid(&self, current_direction: Direction) -> Option<Direction> {
+        let direction = match self {
+            GameEvent::Up => Some(Direction::Up),
+            GameEvent::Right => Some(Direction::Right),
+            GameEvent::Down => Some(Direction::Down),
+            GameEvent::Left => Some(Direction::Left),
+            _ => None
+        };
+
+        direction.and_then(|d| Direction::get_valid_transition(current_direction, d))
+    }
+
+    /// Whether the event influences the simulation and therefore has to go
+    /// into the input log for a replay.
+    fn is_recorded(&self) -> bool {
+        !matches!(self, GameEvent::Timeout | GameEvent::Redraw | GameEvent::Submit | GameEvent::NextMode | GameEvent::Quit)
+    }
+}
+
+/// Where the snake heads, and the action an [`env::Env`] takes.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Direction {
+    Up, Right, Down, Left
+}
+
+impl Direction {
+    fn get_valid_transition(current: Direction, next: Direction) -> Option<Direction> {
+        match (current, next) {
+            (Direction::Up | Direction::Down, Direction::Left | Direction::Right) => Some(next),
+            (Direction::Right | Direction::Left, Direction::Up | Direction::Down) => Some(next),
+            _ => None
+        }
+    }
+
+    fn as_integer(&self) -> i32 {
+        match self {
+            Direction::Up | Direction::Left => -1,
+            Direction::Down | Direction::Right => 1
+        }
+    }
+}
+
+impl Distribution<Direction> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Direction {
+        match rng.gen_range(0..=3) {
+            0 => Direction::Up,
+            1 => Direction::Right,
+            2 => Direction::Down,
+            3 => Direction::Left,
+            _ => unreachable!()
+        }
+    }
+}
+
+type GameRng = ChaCha8Rng;
+
+/// An event as it was fed to `handle_event`, together with the number of
+/// ticks that had passed before it arrived.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct InputRecord {
+    tick: u64,
+    event: GameEvent
+}
+
+#[derive(Serialize)]
+struct GameState {
+    snake_direction: Direction,
+    snake: Snake,
+    number_pos: (usize, usize),
+    current_number: u8,
+    extending: u8,
+    paused: bool,
+    score: i32,
+    lives: u8,
+    level: u8,
+    cached_board: Option<Rc<Board>>,
+    /// The computer-controlled snakes in battle mode.
+    opponents: Vec<battle::Opponent>,
+    duration_since_last_update: Duration,
+    show_frame_time: bool,
+    /// Whether this is the attract-mode demo rather than a game someone plays.
+    demo: bool,
+    mode: modes::Mode,
+    /// Ticks played so far, for modes against the clock.
+    clock: u64,
+    /// `clock` at every number eaten, in modes that keep splits.
+    splits: Vec<u64>,
+    /// Tenths of a move the snakes have to make yet, see `Rules::pace`.
+    moves: u32,
+    /// Whether snakes leave trails that never shrink instead of going for
+    /// numbers, and score for every move they survive.
+    light_cycle: bool,
+    terminal_size: (u16, u16),
+    /// What happened since the control clients were last told, if there
+    /// are any to tell.
+    #[serde(skip)]
+    notices: Option<Vec<control::Notice>>,
+    /// The layout from `--level` that every level has in place of its own.
+    level_file: Option<Rc<Board>>,
+    /// Where numbers go in a race, in place of `rng`, see `race::number_rng`.
+    race_seed: Option<u64>,
+    seed: u64,
+    #[serde(skip)]
+    rng: GameRng,
+    ticks: u64,
+    input_log: Vec<InputRecord>
+}
+
+#[derive(Clone, Copy, Debug, std::cmp::Eq, std::cmp::PartialEq, Serialize)]
+struct SnakeCell(i32, i32);
+
+#[derive(Default)]
+struct Options {
+    replay: Option<PathBuf>,
+    bench: bool,
+    no_sync: bool,
+    no_animations: bool,
+    autoplay: bool,
+    bandwidth: Option<usize>,
+    bot: Option<bot::Connection>,
+    bot_deadline: Option<u64>,
+    bot_games: Option<u32>,
+    opponents: usize,
+    difficulty: battle::Difficulty,
+    serve: Option<u16>,
+    spectate: Option<spectate::Address>,
+    spectate_public: bool,
+    control_socket: Option<PathBuf>,
+    leaderboard: Option<u16>,
+    submit_to: Option<String>,
+    name: Option<String>,
+    race: bool,
+    light_cycle: bool,
+    rival: bool,
+    mode: modes::Mode,
+    generate: Option<procedural::Algorithm>,
+    density: Option<f32>,
+    seed: Option<u64>,
+    level: Option<PathBuf>
+}
+
+fn parse_options() -> Options {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--replay" => options.replay = Some(option_value(&arg, args.next())),
+            "--bench" => options.bench = true,
+            "--no-sync" => options.no_sync = true,
+            "--no-animations" => options.no_animations = true,
+            "--autoplay" => options.autoplay = true,
+            "--bandwidth" => options.bandwidth = Some(option_value(&arg, args.next())),
+            "--bot" => options.bot = Some(bot::Connection::Command(option_value(&arg, args.next()))),
+            "--bot-socket" => options.bot = Some(bot::Connection::Socket(option_value(&arg, args.next()))),
+            "--bot-deadline" => options.bot_deadline = Some(option_value(&arg, args.next())),
+            "--bot-games" => options.bot_games = Some(option_value(&arg, args.next())),
+            "--opponents" => match option_value(&arg, args.next()) {
+                count @ 1..=battle::MAX_OPPONENTS => options.opponents = count,
+                _ => {
+                    eprintln!("{arg} takes 1 to {}", battle::MAX_OPPONENTS);
+                    std::process::exit(2);
+                }
+            },
+            "--difficulty" => options.difficulty = option_value(&arg, args.next()),
+            "--serve" => options.serve = Some(option_value(&arg, args.next())),
+            "--spectate-port" => {
+                let port: u16 = option_value(&arg, args.next());
+                options.spectate = Some(spectate::Address::Tcp(SocketAddr::from(([127, 0, 0, 1], port))));
+            }
+            "--spectate-public" => options.spectate_public = true,
+            "--spectate-socket" => options.spectate = Some(spectate::Address::Socket(option_value(&arg, args.next()))),
+            "--control-socket" => options.control_socket = Some(option_value(&arg, args.next())),
+            "--leaderboard" => options.leaderboard = Some(option_value(&arg, args.next())),
+            "--submit-to" => options.submit_to = Some(option_value(&arg, args.next())),
+            "--name" => options.name = Some(option_value(&arg, args.next())),
+            "--race" => options.race = true,
+            "--light-cycle" => options.light_cycle = true,
+            "--rival" => options.rival = true,
+            "--mode" => options.mode = option_value(&arg, args.next()),
+            "--generate" => options.generate = Some(option_value(&arg, args.next())),
+            "--density" => match option_value(&arg, args.next()) {
+                density if (0.0..=1.0).contains(&density) => options.density = Some(density),
+                _ => {
+                    eprintln!("{arg} takes 0 to 1");
+                    std::process::exit(2);
+                }
+            },
+            "--seed" => options.seed = Some(option_value(&arg, args.next())),
+            "--level" => options.level = Some(option_value(&arg, args.next())),
+            _ => {
+                eprintln!("unknown argument: {arg}");
+                std::process::exit(2);
+            }
+        }
+    }
+    if options.spectate_public {
+        match &mut options.spectate {
+            Some(spectate::Address::Tcp(address)) => address.set_ip(Ipv4Addr::UNSPECIFIED.into()),
+            _ => {
+                eprintln!("--spectate-public needs --spectate-port");
+                std::process::exit(2);
+            }
+        }
+    }
+    options
+}
+
+fn option_value<T: std::str::FromStr>(arg: &str, value: Option<String>) -> T {
+    match value.map(|value| value.parse()) {
+        Some(Ok(value)) => value,
+        _ => {
+            eprintln!("{arg} needs a valid value");
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Plays, or does whatever else the command line asks for.
+pub fn run() {
+    let options = parse_options();
+    if let Some(path) = options.replay {
+        crash::replay(&path).unwrap();
+        return;
+    }
+    if options.bench {
+        bench::run();
+        return;
+    }
+    if let Some(connection) = options.bot {
+        // a tick's worth of thinking, unless the bot needs more
+        let deadline = options.bot_deadline.map_or(UPDATE_TIME, Duration::from_millis);
+        if let Err(err) = bot::run(connection, deadline, options.bot_games.unwrap_or(1)) {
+            eprintln!("bot failed: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(port) = options.serve {
+        if let Err(err) = server::run(port, options.mode) {
+            eprintln!("server failed: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(algorithm) = options.generate {
+        // sized for this terminal, if there is one
+        let terminal_size = crossterm::terminal::size().unwrap_or((80, 24));
+        let seed = options.seed.unwrap_or_else(rand::random);
+        let mut board = empty_board(terminal_size);
+        procedural::generate(algorithm, &mut board, seed, options.density.unwrap_or(0.2));
+        let path = PathBuf::from(format!("{algorithm}-{seed:016x}.level"));
+        if let Err(err) = std::fs::write(&path, procedural::level_text(&board)) {
+            eprintln!("can't write {}: {err}", path.display());
+            std::process::exit(1);
+        }
+        println!("{}", path.display());
+        return;
+    }
+    if let Some(port) = options.leaderboard {
+        if let Err(err) = leaderboard::run(port) {
+            eprintln!("leaderboard failed: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // told before the game takes over the screen, so it's there after quitting
+    let spectators = options.spectate.map(|address| {
+        let spectators = spectate::Spectators::bind(&address, !options.no_sync).unwrap_or_else(|err| {
+            eprintln!("can't listen for spectators: {err}");
+            std::process::exit(1);
+        });
+        if let Ok(address) = spectators.address() {
+            eprintln!("spectators can watch on {address}");
+        }
+        Rc::new(RefCell::new(spectators))
+    });
+    let control = options.control_socket.map(|path| {
+        let control = control::Control::bind(&path).unwrap_or_else(|err| {
+            eprintln!("can't listen on {}: {err}", path.display());
+            std::process::exit(1);
+        });
+        Rc::new(RefCell::new(control))
+    });
+    let level_file = options.level.map(|path| {
+        let level = std::fs::read_to_string(&path).map_err(|err| err.to_string()).and_then(|text| procedural::parse_level(&text));
+        Rc::new(level.unwrap_or_else(|err| {
+            eprintln!("can't play {}: {err}", path.display());
+            std::process::exit(1);
+        }))
+    });
+    let player = options.name.or_else(|| std::env::var("USER").ok()).unwrap_or_else(|| "anonymous".to_string());
+    let leaderboard = options.submit_to.map(|address| leaderboard::Client::new(address, player.clone()));
+
+    crash::install_panic_hook();
+    let mut backend = CrosstermBackend::new(stdout());
+    if let Some(bytes_per_second) = options.bandwidth {
+        backend = backend.bandwidth(bytes_per_second, UPDATE_TIME);
+    }
+    let guard = TerminalGuard::enter().unwrap();
+    // only for terminals that say they know it
+    backend = backend.synchronized(!options.no_sync && guard::synchronized_output_supported());
+
+    if options.race {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| race::run(&guard, &mut backend)));
+        drop(guard);
+        if let Err(payload) = result {
+            panic::resume_unwind(payload);
+        }
+        return;
+    }
+
+    let mut game_state = create_game_state(rand::random(), crossterm::terminal::size().unwrap());
+    battle::add_opponents(&mut game_state, options.opponents, options.difficulty);
+    if options.rival {
+        battle::add_rival(&mut game_state);
+    }
+    game_state.light_cycle = options.light_cycle;
+    game_state.mode = options.mode;
+    game_state.level_file = level_file;
+    if control.is_some() {
+        game_state.notices = Some(Vec::new());
+    }
+    // the frame time changes every tick, which is a waste on a slow link
+    game_state.show_frame_time = options.bandwidth.is_none();
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        // animations are cheap, but not free on a slow link
+        let mut renderer = Renderer::new().animated(!options.no_animations && options.bandwidth.is_none());
+        let mut peers: Vec<Box<dyn Peer>> = Vec::new();
+        if let Some(control) = &control {
+            peers.push(Box::new(control::ControlSocket::new(Rc::clone(control))));
+        }
+        let mut broadcast;
+        let backend: &mut dyn Backend = match &spectators {
+            Some(spectators) => {
+                peers.push(Box::new(spectate::SpectatorSocket::new(Rc::clone(spectators))));
+                broadcast = spectate::Broadcast::new(&mut backend, Rc::clone(spectators));
+                &mut broadcast
+            }
+            None => &mut backend
+        };
+        let services = Services { peers, control: control.as_deref(), leaderboard, player };
+        listen_for_events(&mut game_state, &guard, &mut renderer, backend, options.autoplay, services);
+    }));
+    drop(guard);
+
+    if options.bandwidth.is_some() {
+        eprintln!("{}", backend.throughput());
+    }
+    if let Err(payload) = result {
+        match crash::write_bundle(&game_state) {
+            Ok(path) => eprintln!("crash report written to {}", path.display()),
+            Err(err) => eprintln!("failed to write crash report: {err}")
+        }
+        panic::resume_unwind(payload);
+    }
+}
+
+fn spawn_number(board: &Board, rng: &mut GameRng) -> (usize, usize) {
+    loop {
+        let pos = (
+            rng.gen_range(1..board.width - 1),
+            rng.gen_range(1..board.height - 2)
+        );
+
+        if board.lookup(pos.0, pos.1) < 0 {
+            return pos;
+        }
+    }
+}
+
+const UPDATE_TIME: Duration = Duration::from_millis(100);
+/// What every move a snake survives is worth in a light-cycle game.
+const LIGHT_CYCLE_POINTS: i32 = 10;
+/// The shortest time between two frames. Frames are only drawn when something
+/// changed, so this caps the frame rate at 60 fps rather than setting it.
+const FRAME_TIME: Duration = Duration::from_micros(16_667);
+/// How long the title screen waits for a key before the demo starts.
+const DEMO_IDLE_TIME: Duration = Duration::from_secs(20);
+/// What the game loop talks to besides the terminal.
+struct Services<'a> {
+    peers: Vec<Box<dyn Peer>>,
+    control: Option<&'a RefCell<control::Control>>,
+    leaderboard: Option<leaderboard::Client>,
+    /// The name scores go under.
+    player: String
+}
+
+/// The line under the game over prompt while a lost game can be submitted.
+const SUBMIT_NOTE: &str = "Press ENTER to submit your score";
+const SUBMITTING_NOTE: &str = "Submitting...";
+
+/// The line under the title screen's prompt.
+fn mode_menu(mode: modes::Mode) -> String {
+    format!("Mode: {mode} (M to change)")
+}
+
+/// The high scores of `mode` on this machine.
+fn local_high_scores(mode: modes::Mode) -> std::io::Result<HighScores> {
+    HighScores::load(data::data_dir()?.join(mode.high_scores_file()))
+}
+
+/// Gets `player`'s best time attack on this machine ready to race against,
+/// if that's what's about to be played.
+fn load_best_splits(mode: modes::Mode, player: &str, renderer: &mut Renderer) {
+    let best = match mode {
+        modes::Mode::TimeAttack => local_high_scores(mode).map(|scores| scores.best_splits(player).to_vec()).unwrap_or_default(),
+        _ => Vec::new()
+    };
+    renderer.set_best_splits(best);
+}
+
+fn listen_for_events(game_state: &mut GameState, guard: &TerminalGuard, renderer: &mut Renderer, backend: &mut dyn Backend, autoplay: bool, services: Services) {
+    let Services { peers, control, mut leaderboard, player } = services;
+    let mut event_loop = EventLoop::new(UPDATE_TIME).unwrap();
+    // a rival takes the arrow keys
+    let keys = if battle::rival(game_state) { race::translate_event } else { translate_event };
+    event_loop.attach(Box::new(TerminalInput::new().unwrap().keys(keys))).unwrap();
+    for peer in peers {
+        event_loop.attach(peer).unwrap();
+    }
+    let mut inputs = Vec::new();
+    let started = Instant::now();
+    let mut last_update = started;
+    // the board of the latest frame, and whether it still has to be drawn
+    let mut shown = shown_board(game_state);
+    let mut dirty = true;
+    let mut next_frame = started;
+    let mut next_animation = None;
+    // the attract-mode game shown instead of ours while nobody plays
+    let mut demo: Option<GameState> = None;
+    let mut title = true;
+    let mut last_input = started;
+    // whether the lost game went to the leaderboard, and into the local
+    // high scores, already
+    let mut submitted = false;
+    let mut recorded = false;
+    if leaderboard.is_some() {
+        renderer.set_game_over_note(Some(SUBMIT_NOTE.to_string()));
+    }
+    renderer.set_menu(Some(mode_menu(game_state.mode)));
+    load_best_splits(game_state.mode, &player, renderer);
+
+    backend.invalidate().unwrap();
+
+    'outer: loop {
+        match guard.check_signals().unwrap() {
+            SignalAction::Quit => break 'outer,
+            SignalAction::Resumed => {
+                demo = None;
+                resume(game_state, renderer, backend);
+                event_loop.reset_clock().unwrap();
+            }
+            SignalAction::Nothing => ()
+        }
+
+        let deadline = if dirty { Some(next_frame) } else { next_animation };
+        let ticks = event_loop.wait(&mut inputs, deadline).unwrap();
+        if let Some(control) = control {
+            control.borrow_mut().serve(game_state, &mut inputs);
+        }
+
+        for event in inputs.drain(..) {
+            if let Some(state) = demo.as_mut() {
+                match event {
+                    GameEvent::Resize(..) | GameEvent::Redraw => {
+                        apply_event(event, game_state, backend);
+                        if let Some(board) = advance(event, state) {
+                            shown = board;
+                            dirty = true;
+                        }
+                        continue;
+                    }
+                    GameEvent::Quit | GameEvent::Suspend => demo = None,
+                    _ => {
+                        // any other key only ends the demo
+                        demo = None;
+                        last_input = Instant::now();
+                        shown = shown_board(game_state);
+                        dirty = true;
+                        continue;
+                    }
+                }
+            }
+            last_input = Instant::now();
+            match event {
+                GameEvent::Quit => break 'outer,
+                GameEvent::Suspend => {
+                    guard.suspend().unwrap();
+                    resume(game_state, renderer, backend);
+                    event_loop.reset_clock().unwrap();
+                }
+                GameEvent::NextMode => {
+                    // only before the game got going
+                    if title {
+                        game_state.mode = game_state.mode.next();
+                        restart(game_state);
+                        renderer.set_menu(Some(mode_menu(game_state.mode)));
+                        load_best_splits(game_state.mode, &player, renderer);
+                        shown = shown_board(game_state);
+                        dirty = true;
+                    }
+                }
+                GameEvent::Submit => {
+                    if let Some(leaderboard) = leaderboard.as_mut().filter(|leaderboard| game_over(game_state) && !submitted && !leaderboard.is_busy()) {
+                        leaderboard.submit(game_state);
+                        renderer.set_game_over_note(Some(SUBMITTING_NOTE.to_string()));
+                        dirty = true;
+                    }
+                }
+                event => {
+                    if let GameEvent::Action = event {
+                        // restarting after a lost game leads back to the title
+                        title = game_over(game_state);
+                        renderer.set_menu(title.then(|| mode_menu(game_state.mode)));
+                        if let Some(leaderboard) = leaderboard.as_mut().filter(|_| title) {
+                            leaderboard.cancel();
+                            submitted = false;
+                            renderer.set_high_scores(Vec::new());
+                            renderer.set_game_over_note(Some(SUBMIT_NOTE.to_string()));
+                        }
+                    }
+                    if let Some(board) = apply_event(event, game_state, backend) {
+                        shown = board;
+                        dirty = true;
+                    }
+                }
+            }
+        }
+
+        for _ in 0..ticks {
+            let now = Instant::now();
+            let playing = demo.as_mut().unwrap_or(game_state);
+            playing.duration_since_last_update = now - last_update;
+            last_update = now;
+            let board = if autoplay || playing.demo {
+                autoplay_tick(playing, backend)
+            } else {
+                apply_event(GameEvent::Timeout, playing, backend)
+            };
+            if let Some(board) = board {
+                shown = board;
+                dirty = true;
+            }
+        }
+
+        if let Some(result) = leaderboard.as_mut().and_then(leaderboard::Client::poll) {
+            let note = match result {
+                Ok(standing) => {
+                    submitted = true;
+                    renderer.set_high_scores(standing.rankings);
+                    standing.summary
+                }
+                Err(err) => format!("Submitting failed: {err}")
+            };
+            renderer.set_game_over_note(Some(note));
+            dirty = true;
+        }
+
+        let now = Instant::now();
+        if title && !autoplay && demo.is_none() && now - last_input >= DEMO_IDLE_TIME {
+            let mut state = create_game_state(rand::random(), game_state.terminal_size);
+            state.show_frame_time = game_state.show_frame_time;
+            state.demo = true;
+            shown = shown_board(&mut state);
+            dirty = true;
+            demo = Some(state);
+        }
+
+        // time attacks are kept on this machine, for the splits of the best
+        // one to race against
+        if !game_over(game_state) {
+            recorded = false;
+        } else if !recorded && !autoplay && game_state.mode == modes::Mode::TimeAttack {
+            recorded = true;
+            record_locally(game_state, &player, renderer);
+            dirty = true;
+        }
+
+        if let Some(control) = control {
+            control.borrow_mut().publish(game_state);
+        }
+
+        let playing = demo.as_ref().unwrap_or(game_state);
+        let (animated, until_animation) = renderer.animate(playing, now - started);
+        dirty |= animated;
+        next_animation = until_animation.map(|until| now + until);
+        if dirty && now >= next_frame {
+            render_frame(playing, &shown, renderer, backend);
+            dirty = false;
+            next_frame = now + FRAME_TIME;
+        }
+    }
+}
+
+/// Puts the lost game into the high scores of its mode on this machine and
+/// lists them on the game over screen.
+fn record_locally(game_state: &GameState, player: &str, renderer: &mut Renderer) {
+    let entry = Entry { name: player.to_string(), score: game_state.score, level: game_state.level, splits: game_state.splits.clone() };
+    // losing the table is no reason to stop playing
+    if let Ok(mut scores) = local_high_scores(game_state.mode) {
+        if scores.record(entry).is_ok() {
+            renderer.set_high_scores(scores.lines());
+            renderer.set_best_splits(scores.best_splits(player).to_vec());
+        }
+    }
+}
+
+/// Lets the autopilot play a tick. It gets going again by itself after a
+/// mistake or a lost game, so it never waits on anyone.
+fn autoplay_tick(game_state: &mut GameState, backend: &mut dyn Backend) -> Option<Rc<Board>> {
+    if game_state.paused {
+        apply_event(GameEvent::Action, game_state, backend);
+    }
+    let board = shown_board(game_state);
+    if let Some(turn) = autopilot::steer(game_state, &board) {
+        apply_event(turn, game_state, backend);
+    }
+    let board = apply_event(GameEvent::Timeout, game_state, backend);
+    if game_over(game_state) {
+        restart(game_state);
+        return Some(shown_board(game_state));
+    }
+    board
+}
+
+/// Pauses the game and repaints everything after we got the terminal back
+/// from the shell, since we can't know what happened to the screen meanwhile.
+fn resume(game_state: &mut GameState, renderer: &mut Renderer, backend: &mut dyn Backend) {
+    handle_event(GameEvent::Suspend, game_state, renderer, backend);
+    handle_event(GameEvent::Redraw, game_state, renderer, backend);
+}
+
+fn translate_event(event: Event) -> Option<GameEvent> {
+    match event {
+        Event::Key(key) if key.modifiers.contains(KeyModifiers::CONTROL) => match key.code {
+            KeyCode::Char('l') => Some(GameEvent::Redraw),
+            KeyCode::Char('z') => Some(GameEvent::Suspend),
+            _ => None
+        }
+        Event::Key(key) => match key.code {
+            KeyCode::Up | KeyCode::Char('w') => Some(GameEvent::Up), 
+            KeyCode::Right | KeyCode::Char('d') => Some(GameEvent::Right),
+            KeyCode::Down | KeyCode::Char('s') => Some(GameEvent::Down),
+            KeyCode::Left | KeyCode::Char('a') => Some(GameEvent::Left),
+            KeyCode::Char(' ') => Some(GameEvent::Action),
+            KeyCode::Enter => Some(GameEvent::Submit),
+            KeyCode::Char('m') => Some(GameEvent::NextMode),
+            KeyCode::Char('q') => Some(GameEvent::Quit),
+            KeyCode::Esc => Some(GameEvent::Escape),
+            _ => None
+        }
+        Event::Resize(width, height) => Some(GameEvent::Resize(width, height)),
+        _ => None
+    }
+}
+
+type SpawnFn = fn(&Board, &mut GameRng) -> (Vec<SnakeCell>, Direction);
+type RenderFn = fn(&mut Board);
+
+static LEVELS: &[(SpawnFn, RenderFn)] = &[
+    (spawn_l1, render_l1),
+    (spawn_l2, render_l2),
+    (spawn_l3, render_l3),
+    (spawn_centred, render_l4),
+    (spawn_centred, render_l5),
+    (spawn_centred, render_l6),
+    (spawn_centred, render_l7),
+    (spawn_centred, render_l8)
+];
+
+/// Applies an event and draws the resulting frame right away.
+fn handle_event(event: GameEvent, game_state: &mut GameState, renderer: &mut Renderer, backend: &mut dyn Backend) {
+    if let Some(board) = apply_event(event, game_state, backend) {
+        render_frame(game_state, &board, renderer, backend);
+    }
+}
+
+/// Applies an event, returning the board for the next frame if it needs one.
+/// The backend forgets what's on screen if the event asks for a repaint.
+fn apply_event(event: GameEvent, game_state: &mut GameState, backend: &mut dyn Backend) -> Option<Rc<Board>> {
+    if let GameEvent::Redraw | GameEvent::Resize(..) = event {
+        backend.invalidate().unwrap();
+    }
+    advance(event, game_state)
+}
+
+/// Applies an event to the game state, returning the board to display when
+/// the event calls for a new frame. Everything in here has to stay
+/// deterministic given the seed and the input log, see `crash::replay`.
+fn advance(event: GameEvent, game_state: &mut GameState) -> Option<Rc<Board>> {
+    if event.is_recorded() {
+        game_state.input_log.push(InputRecord { tick: game_state.ticks, event });
+    }
+
+    // handle events
+    match event {
+        GameEvent::Up | GameEvent::Right | GameEvent::Down | GameEvent::Left => {
+            if let Some(new_direction) = event.map_to_direction_check_valid(game_state.snake_direction) {
+                game_state.snake_direction = new_direction;
+            }
+        }
+        GameEvent::Action | GameEvent::Second(race::Key::Action) if game_over(game_state) => {
+            restart(game_state);
+            return Some(shown_board(game_state));
+        }
+        // there's no playing blind on a terminal too small to show the game
+        GameEvent::Action | GameEvent::Second(race::Key::Action) if game_state.paused && !too_small(game_state.terminal_size) => {
+            game_state.paused = false;
+            return Some(shown_board(game_state));
+        }
+        GameEvent::Escape | GameEvent::Suspend if !game_state.paused => {
+            game_state.paused = true;
+            return Some(shown_board(game_state));
+        }
+        GameEvent::Resize(width, height) => {
+            game_state.terminal_size = (width, height);
+            game_state.cached_board = None;
+            let board = level_board(game_state);
+            fit_to_board(game_state, &board);
+            check_unitialized_state(game_state, &board);
+            if too_small(game_state.terminal_size) {
+                game_state.paused = true;
+            }
+            return Some(board);
+        }
+        GameEvent::Second(key) => {
+            if let Some(direction) = key.direction() {
+                battle::turn_rival(game_state, direction);
+            }
+        }
+        GameEvent::Redraw => {
+            return Some(shown_board(game_state));
+        }
+        GameEvent::Timeout => {
+            game_state.ticks += 1;
+            let board = level_board(game_state);
+
+            check_unitialized_state(game_state, &board);
+
+            if !game_over(game_state) && !game_state.paused {
+                let rules = game_state.mode.rules();
+                rules.tick(game_state);
+                game_state.moves += rules.pace(game_state);
+                // a mistake or a finished level stops the moves left over
+                while game_state.moves >= 10 && !game_over(game_state) && !game_state.paused {
+                    game_state.moves -= 10;
+                    update(game_state, &board);
+                }
+            }
+
+            check_unitialized_state(game_state, &board);
+
+            return Some(board);
+        }
+        _ => ()
+    };
+    None
+}
+
+/// Returns the board for a frame that doesn't follow a tick, spawning the snake
+/// and the number first if they are missing.
+fn shown_board(game_state: &mut GameState) -> Rc<Board> {
+    let board = level_board(game_state);
+    check_unitialized_state(game_state, &board);
+    board
+}
+
+/// Returns the current level's board, rendering and caching it first if the
+/// level changed since the last call.
+fn level_board(game_state: &mut GameState) -> Rc<Board> {
+    match &game_state.cached_board {
+        None => {
+            let mut board = empty_board(game_state.terminal_size);
+            match &game_state.level_file {
+                Some(level) => procedural::lay_out(&mut board, level),
+                None => game_state.mode.rules().render(game_state, &mut board)
+            }
+            let board = Rc::new(board);
+            game_state.cached_board = Some(Rc::clone(&board));
+            board
+        },
+        Some(board) => Rc::clone(board)
+    }
+}
+
+/// A board without walls that fills the playfield of a terminal this size.
+fn empty_board(terminal_size: (u16, u16)) -> Board {
+    let (width, height) = board_size(terminal_size);
+    Board::new(width, height)
+}
+
+/// The width and height of the board for a terminal this size.
+fn board_size(terminal_size: (u16, u16)) -> (usize, usize) {
+    let Rect { top, left, right, bottom } = get_playfield(terminal_size);
+    ((right - left) as usize + 1, (bottom - top) as usize + 1)
+}
+
+fn render_frame(game_state: &GameState, board: &Rc<Board>, renderer: &mut Renderer, backend: &mut dyn Backend) {
+    let frame = renderer.render(game_state, board);
+    backend.draw(frame).unwrap();
+}
+
+fn check_unitialized_state(game_state: &mut GameState, board: &Board) {
+    // there's nothing to eat in a light-cycle game
+    if (game_state.number_pos.0 == 0 || game_state.number_pos.1 == 0) && !game_state.light_cycle {
+        game_state.number_pos = match game_state.race_seed {
+            Some(seed) => spawn_number(board, &mut race::number_rng(seed, game_state.level, game_state.current_number)),
+            None => spawn_number(board, &mut game_state.rng)
+        };
+    }
+    if game_state.snake.is_empty() {
+        let (vec, dir) = match game_state.level_file {
+            // level files keep the middle clear
+            Some(_) => spawn_centred(board, &mut game_state.rng),
+            None => game_state.mode.rules().spawn(game_state, board)
+        };
+        game_state.snake.reset(vec);
+        game_state.snake_direction = dir;
+        battle::spawn(game_state, board);
+    }
+}
+
+/// Drops the snakes and the number if they ended up outside of the free area
+/// after the board was rebuilt for a new terminal size, so that they get
+/// spawned again.
+fn fit_to_board(game_state: &mut GameState, board: &Board) {
+    let is_free = |x: i32, y: i32| {
+        x >= 1 && y >= 1 && x < board.width as i32 && y <= board.height as i32
+            && board.lookup(x as usize, y as usize) < 0
+    };
+    if !game_state.snake.iter().all(|&SnakeCell(x, y)| is_free(x, y)) || !battle::fit(game_state, board) {
+        game_state.snake.clear();
+        battle::clear(game_state);
+        game_state.extending = 0;
+        game_state.paused = true;
+    }
+    let (x, y) = game_state.number_pos;
+    if x >= board.width - 1 || y >= board.height - 2 || !is_free(x as i32, y as i32) {
+        game_state.number_pos = (0, 0);
+    }
+}
+
+/// Replaces the finished run with a new one, keeping what isn't part of the
+/// game itself.
+fn restart(game_state: &mut GameState) {
+    let (show_frame_time, demo, light_cycle, mode) = (game_state.show_frame_time, game_state.demo, game_state.light_cycle, game_state.mode);
+    let notices = game_state.notices.take();
+    let level_file = game_state.level_file.take();
+    let (opponents, rival) = (battle::setup(game_state), battle::rival(game_state));
+    *game_state = create_game_state(rand::random(), game_state.terminal_size);
+    game_state.show_frame_time = show_frame_time;
+    game_state.demo = demo;
+    game_state.light_cycle = light_cycle;
+    game_state.mode = mode;
+    game_state.notices = notices;
+    game_state.level_file = level_file;
+    if let Some((count, difficulty)) = opponents {
+        battle::add_opponents(game_state, count, difficulty);
+    }
+    if rival {
+        battle::add_rival(game_state);
+    }
+}
+
+/// Starts a new run. The input log begins with the terminal size, so a replay
+/// sizes the board the same way before the first tick.
+fn create_game_state(seed: u64, terminal_size: (u16, u16)) -> GameState {
+    GameState {
+        snake_direction: Direction::Right,
+        snake: Snake::default(),
+        number_pos: (0, 0),
+        current_number: 1,
+        extending: 0,
+        paused: true,
+        score: 0,
+        lives: 5,
+        level: 1,
+        cached_board: None,
+        opponents: Vec::new(),
+        duration_since_last_update: Duration::from_millis(1),
+        show_frame_time: true,
+        demo: false,
+        light_cycle: false,
+        mode: modes::Mode::default(),
+        clock: 0,
+        splits: Vec::new(),
+        moves: 0,
+        terminal_size,
+        notices: None,
+        level_file: None,
+        race_seed: None,
+        seed,
+        rng: GameRng::seed_from_u64(seed),
+        ticks: 0,
+        input_log: vec![InputRecord { tick: 0, event: GameEvent::Resize(terminal_size.0, terminal_size.1) }]
+    }
+}
+
+fn update(game_state: &mut GameState, board: &Board) {
+    if game_state.light_cycle {
+        game_state.snake.grow();
+    } else if game_state.extending != 0 {
+        game_state.snake.grow();
+        game_state.extending -= 1;
+    }
+
+    let mut head = game_state.snake.head();
+
+    // move the snake accroding to Direction
+    match game_state.snake_direction {
+        Direction::Left | Direction::Right => {
+            head.0 += game_state.snake_direction.as_integer();
+        }
+        Direction::Up | Direction::Down => {
+            head.1 += game_state.snake_direction.as_integer();
+        }
+    }
+
+    if head.0 < 1 || head.1 < 1 || head.0 >= board.width as i32 || head.1 > board.height as i32 {
+        did_make_mistake(game_state);
+        return;
+    }
+
+    if board.lookup(head.0 as usize, head.1 as usize) >= 0 {
+        did_make_mistake(game_state);
+        return;
+    }
+
+    if battle::occupied(game_state, head, board) {
+        did_make_mistake(game_state);
+        return;
+    }
+
+    if !game_state.snake.advance(head, board) {
+        did_make_mistake(game_state);
+        return;
+    }
+
+    if game_state.light_cycle {
+        game_state.score += LIGHT_CYCLE_POINTS;
+    }
+
+    let number = game_state.number_pos;
+
+    if (head.0 as usize, (head.1 - head.1 % 2) as usize) == (number.0, number.1 - number.1 % 2)  {
+        let number = game_state.current_number;
+        if game_state.mode.rules().food_eaten(game_state) {
+            finish_level(game_state);
+            return;
+        }
+        control::notify(game_state, control::Notice::Food { number, score: game_state.score });
+        game_state.number_pos = (0, 0);
+    }
+
+    match battle::update(game_state, board) {
+        battle::Round::Continues => (),
+        battle::Round::Won => finish_level(game_state),
+        battle::Round::Lost => did_make_mistake(game_state)
+    }
+}
+
+fn finish_level(game_state: &mut GameState) {
+    game_state.mode.rules().level_complete(game_state);
+    game_state.snake.clear();
+    battle::clear(game_state);
+    game_state.snake_direction = Direction::Right;
+    game_state.number_pos = (0, 0);
+    game_state.extending = 0;
+    game_state.paused = true;
+    game_state.cached_board = None;
+    control::notify(game_state, control::Notice::Level { level: game_state.level, score: game_state.score });
+}
+
+fn did_make_mistake(game_state: &mut GameState) {
+    game_state.mode.rules().collision(game_state);
+    if !game_over(game_state) {
+        game_state.snake.clear();
+        battle::clear(game_state);
+        game_state.number_pos = (0, 0);
+        game_state.snake_direction = Direction::Right;
+        game_state.extending = 0;
+    }
+    game_state.paused = true;
+    control::notify(game_state, control::Notice::LifeLost { lives: game_state.lives, score: game_state.score });
+}
+
+#[derive(Clone, Serialize)]
+struct Board {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) rows: Vec<Vec<i16>>
+}
+
+impl Board {
+    fn new(width: usize, height: usize) -> Self {
+        Self::new_init(width, height, -1)
+    }
+
+    fn new_init(width: usize, height: usize, init: i16) -> Board {
+        Board {
+            width,
+            height,
+            rows: vec![vec![init; width]; height + 1]
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, val: i16) {
+        self.rows[y][x] = val;
+    }
+
+    fn iter(&self) -> BoardIterator<'_> {
+        BoardIterator::new(self)
+    }
+
+    fn lookup(&self, x: usize, y: usize) -> i16 {
+        self.rows[y][x]
+    }
+}
+
+struct BoardIterator<'a>(&'a Board, usize);
+
+impl<'a> BoardIterator<'a> {
+    fn new(board: &'a Board) -> BoardIterator<'a> {
+        BoardIterator(board, 0)
+    }
+}
+
+impl<'a> Iterator for BoardIterator<'a> {
+    type Item = (&'a [i16], Option<&'a [i16]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let max = self.0.rows.len();
+        if self.1 >= max {
+            return None;
+        }
+        
+        let end = !max.is_multiple_of(2) && self.1 + 1 >= max;
+        let res = ( 
+            self.0.rows[self.1].as_ref(),
+            if !end { Some(self.0.rows[self.1 + 1].as_ref()) } else { None },
+        );
+        self.1 += 2;
+        Some(res)
+    }
+}
+
+fn game_over(game_state: &GameState) -> bool {
+    game_state.lives == 0
+}
+
+struct Rect {
+    top: i32,
+    left: i32,
+    right: i32,
+    bottom: i32,
+}
+
+/// Whether a terminal this size is too small to show the game on. The game
+/// is played on a board for `MIN_SIZE` underneath, paused until there's room.
+fn too_small(terminal_size: (u16, u16)) -> bool {
+    terminal_size.0 < MIN_SIZE.0 || terminal_size.1 < MIN_SIZE.1
+}
+
+fn get_playfield(terminal_size: (u16, u16)) -> Rect {
+    let (width, height) = (terminal_size.0.max(MIN_SIZE.0), terminal_size.1.max(MIN_SIZE.1));
+    let (width, height) = (width as i32, height as i32 * 2);
+    Rect {
+        top: 4,
+        left: 2,
+        right: width,
+        bottom: height - 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shrinking_the_terminal_respawns_what_no_longer_fits() {
+        let mut game_state = create_game_state(4, (120, 40));
+        advance(GameEvent::Action, &mut game_state);
+        game_state.snake.reset(vec![SnakeCell(100, 60), SnakeCell(99, 60)]);
+        game_state.number_pos = (110, 70);
+
+        let board = advance(GameEvent::Resize(50, 20), &mut game_state).unwrap();
+        assert_eq!((board.width, board.height), (49, 36));
+        assert!(game_state.paused);
+        assert!(game_state.snake.iter().all(|&SnakeCell(x, y)| x < 49 && y <= 36));
+        assert!(game_state.number_pos.0 < 49 && game_state.number_pos.1 <= 36);
+
+        advance(GameEvent::Action, &mut game_state);
+        for _ in 0..10 {
+            advance(GameEvent::Timeout, &mut game_state);
+        }
+    }
+
+    #[test]
+    fn a_terminal_too_small_shows_a_notice_and_holds_the_game() {
+        let text = |frame: &terminal::Terminal| frame.rows.iter().map(|row| row.iter().map(|cell| cell.data).collect::<String>()).collect::<String>();
+        let mut renderer = Renderer::new();
+        let mut game_state = create_game_state(4, (12, 4));
+        let board = shown_board(&mut game_state);
+        renderer.render(&game_state, &board);
+
+        advance(GameEvent::Resize(80, 24), &mut game_state);
+        advance(GameEvent::Action, &mut game_state);
+        assert!(!game_state.paused);
+        for size in [(20, 8), (0, 0), (39, 24)] {
+            let board = advance(GameEvent::Resize(size.0, size.1), &mut game_state).unwrap();
+            assert!(game_state.paused);
+            advance(GameEvent::Action, &mut game_state);
+            assert!(game_state.paused, "played on blind at {size:?}");
+            let frame = renderer.render(&game_state, &board);
+            assert_eq!(frame.size, (size.0 as usize, size.1 as usize));
+            if size.0 > 0 {
+                assert!(text(frame).contains("Terminal too small"));
+            }
+        }
+
+        let board = advance(GameEvent::Resize(40, 16), &mut game_state).unwrap();
+        advance(GameEvent::Action, &mut game_state);
+        assert!(!game_state.paused);
+        assert!(text(renderer.render(&game_state, &board)).contains("Lives"));
+    }
+}
@@ -0,0 +1,367 @@
+//! Game modes: what numbers are worth, how far the snake grows, what a
+//! mistake costs and where a finished level leads. The moving and the
+//! crashing are the same for all of them, the rules are one `Rules` each.
+//!
+//! Every mode has its own high scores, since points from one don't compare
+//! to points from another.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::levels::spawn_centred;
+use crate::{Board, Direction, GameState, LEVELS, SnakeCell, UPDATE_TIME, procedural};
+
+/// Picked with `--mode` or from the title screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Mode {
+    /// Numbers 1 to 10 on each level in turn, five lives.
+    #[default]
+    Campaign,
+    /// A new generated level after every 10 numbers, on and on.
+    Endless,
+    /// Once through every level against the clock.
+    TimeAttack,
+    /// Nothing to lose.
+    Zen
+}
+
+/// The modes in the order the title screen goes through them.
+pub(crate) const MODES: [Mode; 4] = [Mode::Campaign, Mode::Endless, Mode::TimeAttack, Mode::Zen];
+
+impl Mode {
+    pub(crate) fn rules(self) -> &'static dyn Rules {
+        match self {
+            Mode::Campaign => &Campaign,
+            Mode::Endless => &Endless,
+            Mode::TimeAttack => &TimeAttack,
+            Mode::Zen => &Zen
+        }
+    }
+
+    /// The one after this on the title screen.
+    pub(crate) fn next(self) -> Mode {
+        let index = MODES.iter().position(|&mode| mode == self).unwrap();
+        MODES[(index + 1) % MODES.len()]
+    }
+
+    /// Where the mode's high scores go in the data directory. The campaign
+    /// keeps the name it had before there were modes.
+    pub(crate) fn high_scores_file(self) -> String {
+        match self {
+            Mode::Campaign => "highscores.json".to_string(),
+            mode => format!("highscores-{mode}.json")
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Mode, ()> {
+        MODES.into_iter().find(|mode| mode.to_string() == name).ok_or(())
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Mode::Campaign => "campaign",
+            Mode::Endless => "endless",
+            Mode::TimeAttack => "time-attack",
+            Mode::Zen => "zen"
+        })
+    }
+}
+
+/// The hooks the simulation calls into. They only touch the score, the
+/// lives, the level, the numbers and the clock, and say what a level looks
+/// like; the snakes and the board are taken care of around them.
+pub(crate) trait Rules {
+    /// Every tick the game runs, before anything moves.
+    fn tick(&self, _game_state: &mut GameState) {}
+
+    /// How many moves the snakes make every ten ticks.
+    fn pace(&self, _game_state: &GameState) -> u32 {
+        10
+    }
+
+    /// Draws the walls of `game_state.level` onto the empty `board`.
+    fn render(&self, game_state: &GameState, board: &mut Board) {
+        LEVELS[game_state.level as usize - 1].1(board);
+    }
+
+    /// Where the player starts on `board`, and which way.
+    fn spawn(&self, game_state: &mut GameState, board: &Board) -> (Vec<SnakeCell>, Direction) {
+        LEVELS[game_state.level as usize - 1].0(board, &mut game_state.rng)
+    }
+
+    /// The player ate `game_state.current_number`. Returns whether that
+    /// finished the level, otherwise the rules move on to the next number.
+    fn food_eaten(&self, game_state: &mut GameState) -> bool;
+
+    /// The player crashed, or lost the round to an opponent.
+    fn collision(&self, game_state: &mut GameState);
+
+    /// The level is done. Picks the next one.
+    fn level_complete(&self, game_state: &mut GameState);
+
+    /// The left part of the HUD while the game runs.
+    fn hud(&self, game_state: &GameState) -> String;
+}
+
+struct Campaign;
+
+impl Rules for Campaign {
+    fn food_eaten(&self, game_state: &mut GameState) -> bool {
+        if game_state.current_number == 10 {
+            return true;
+        }
+        game_state.score += game_state.current_number as i32 * 100;
+        game_state.extending = game_state.current_number * 4;
+        game_state.current_number += 1;
+        false
+    }
+
+    fn collision(&self, game_state: &mut GameState) {
+        game_state.lives -= 1;
+        if game_state.lives > 0 {
+            game_state.score -= 1000;
+            game_state.current_number = 1;
+        }
+    }
+
+    fn level_complete(&self, game_state: &mut GameState) {
+        // back to the first level after the last one
+        game_state.level = game_state.level % LEVELS.len() as u8 + 1;
+        game_state.current_number = 1;
+        game_state.score += 100;
+    }
+
+    fn hud(&self, game_state: &GameState) -> String {
+        format!("Lives: {}        Level: {}", game_state.lives, game_state.level)
+    }
+}
+
+/// Generated levels without an end. Every level is a stage, with more walls
+/// than the last and a faster snake, up to twice the usual speed.
+struct Endless;
+
+impl Rules for Endless {
+    fn pace(&self, game_state: &GameState) -> u32 {
+        10 + (game_state.level as u32 - 1).min(10)
+    }
+
+    fn render(&self, game_state: &GameState, board: &mut Board) {
+        procedural::render_stage(board, game_state.seed, game_state.level);
+    }
+
+    fn spawn(&self, game_state: &mut GameState, board: &Board) -> (Vec<SnakeCell>, Direction) {
+        // the generator keeps the middle clear
+        spawn_centred(board, &mut game_state.rng)
+    }
+
+    fn food_eaten(&self, game_state: &mut GameState) -> bool {
+        Campaign.food_eaten(game_state)
+    }
+
+    fn collision(&self, game_state: &mut GameState) {
+        Campaign.collision(game_state);
+    }
+
+    fn level_complete(&self, game_state: &mut GameState) {
+        game_state.level = game_state.level.saturating_add(1);
+        game_state.current_number = 1;
+        game_state.score += 100;
+    }
+
+    fn hud(&self, game_state: &GameState) -> String {
+        format!("Lives: {}        Stage: {}", game_state.lives, game_state.level)
+    }
+}
+
+/// How long a time attack may take at most, in ticks.
+const TIME_LIMIT: u64 = 6000;
+/// What a mistake adds to the clock, in ticks.
+const TIME_PENALTY: u64 = 50;
+
+/// Whether a time attack with `splits` got through every level, rather than
+/// running out of time.
+pub(crate) fn time_attack_finished(splits: &[u64]) -> bool {
+    // ten numbers a level
+    splits.len() == LEVELS.len() * 10
+}
+
+/// Every level once, as fast as possible. Mistakes cost time rather than
+/// lives, and the score is what's left of `TIME_LIMIT` at the end, ten points
+/// a tick. Every number eaten is a split, which the HUD holds against the
+/// same one in the best run so far.
+struct TimeAttack;
+
+impl Rules for TimeAttack {
+    fn tick(&self, game_state: &mut GameState) {
+        game_state.clock += 1;
+        if game_state.clock >= TIME_LIMIT {
+            game_state.lives = 0;
+        }
+    }
+
+    fn food_eaten(&self, game_state: &mut GameState) -> bool {
+        game_state.splits.push(game_state.clock);
+        if game_state.current_number == 10 {
+            return true;
+        }
+        game_state.extending = game_state.current_number * 4;
+        game_state.current_number += 1;
+        false
+    }
+
+    fn collision(&self, game_state: &mut GameState) {
+        game_state.clock += TIME_PENALTY;
+    }
+
+    fn level_complete(&self, game_state: &mut GameState) {
+        game_state.current_number = 1;
+        if game_state.level as usize == LEVELS.len() {
+            game_state.score = TIME_LIMIT.saturating_sub(game_state.clock) as i32 * 10;
+            game_state.lives = 0;
+        } else {
+            game_state.level += 1;
+        }
+    }
+
+    fn hud(&self, game_state: &GameState) -> String {
+        let tenths = tenths(game_state.clock as i64);
+        format!("Time: {:>3}.{}      Level: {}", tenths / 10, tenths % 10, game_state.level)
+    }
+}
+
+/// `ticks` in tenths of a second.
+fn tenths(ticks: i64) -> i64 {
+    ticks * UPDATE_TIME.as_millis() as i64 / 100
+}
+
+/// How far `split` is ahead of `best`, or behind it, in seconds.
+pub(crate) fn split_delta(split: u64, best: u64) -> String {
+    let tenths = tenths(split as i64 - best as i64);
+    let sign = if tenths < 0 { '-' } else { '+' };
+    format!("{sign}{}.{}", tenths.abs() / 10, tenths.abs() % 10)
+}
+
+/// The campaign without mistakes: crashing just starts the snake over.
+struct Zen;
+
+impl Rules for Zen {
+    fn food_eaten(&self, game_state: &mut GameState) -> bool {
+        Campaign.food_eaten(game_state)
+    }
+
+    fn collision(&self, _game_state: &mut GameState) {}
+
+    fn level_complete(&self, game_state: &mut GameState) {
+        Campaign.level_complete(game_state);
+    }
+
+    fn hud(&self, game_state: &GameState) -> String {
+        format!("Zen               Level: {}", game_state.level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameEvent, advance, create_game_state, game_over};
+
+    /// Plays until the player made `mistakes` mistakes, never steering.
+    fn crash(mode: Mode, mistakes: usize) -> GameState {
+        let mut game_state = create_game_state(3, (80, 24));
+        game_state.mode = mode;
+        for _ in 0..mistakes {
+            advance(GameEvent::Action, &mut game_state);
+            while !game_state.paused {
+                advance(GameEvent::Timeout, &mut game_state);
+            }
+        }
+        game_state
+    }
+
+    #[test]
+    fn mistakes_cost_what_the_mode_says() {
+        let campaign = crash(Mode::Campaign, 2);
+        assert_eq!(campaign.lives, 3);
+        assert!(campaign.score <= -2000);
+
+        let zen = crash(Mode::Zen, 6);
+        assert_eq!((zen.lives, zen.score), (5, 0));
+        assert!(!game_over(&zen));
+
+        let time_attack = crash(Mode::TimeAttack, 6);
+        assert_eq!(time_attack.lives, 5);
+        assert!(time_attack.clock >= 6 * TIME_PENALTY, "penalties go on the clock");
+    }
+
+    #[test]
+    fn the_campaign_starts_over_after_the_last_level() {
+        let mut game_state = create_game_state(2, (80, 24));
+        game_state.level = LEVELS.len() as u8;
+        game_state.current_number = 10;
+        assert!(Campaign.food_eaten(&mut game_state));
+        crate::finish_level(&mut game_state);
+        assert_eq!((game_state.level, game_state.current_number), (1, 1));
+        // the first level's board and spawn, rather than one past the last
+        advance(GameEvent::Action, &mut game_state);
+        assert!(!game_state.snake.is_empty());
+    }
+
+    #[test]
+    fn endless_stages_go_on_and_speed_up() {
+        let mut game_state = create_game_state(1, (80, 24));
+        game_state.mode = Mode::Endless;
+        game_state.level = LEVELS.len() as u8;
+        game_state.current_number = 10;
+        assert!(Endless.food_eaten(&mut game_state));
+        Endless.level_complete(&mut game_state);
+        assert_eq!((game_state.current_number, game_state.level), (1, LEVELS.len() as u8 + 1));
+        assert_eq!(Endless.pace(&game_state), 18);
+
+        // the snake keeps up with the pace, whatever the ticks
+        game_state.level = 6;
+        advance(GameEvent::Action, &mut game_state);
+        let start = game_state.snake.head();
+        for _ in 0..3 {
+            advance(GameEvent::Timeout, &mut game_state);
+        }
+        let head = game_state.snake.head();
+        assert_eq!((head.0 - start.0).abs() + (head.1 - start.1).abs(), 4);
+        assert_eq!(game_state.moves, 5);
+    }
+
+    #[test]
+    fn time_attacks_end_after_the_last_level() {
+        let mut game_state = create_game_state(1, (80, 24));
+        game_state.level = LEVELS.len() as u8;
+        game_state.clock = TIME_LIMIT - 100;
+        TimeAttack.level_complete(&mut game_state);
+        assert!(game_over(&game_state));
+        assert_eq!(game_state.score, 1000);
+        assert!(game_state.splits.is_empty(), "only numbers eaten are splits");
+        assert_eq!("time-attack".parse(), Ok(Mode::TimeAttack));
+        assert_eq!(Mode::Zen.next(), Mode::Campaign);
+    }
+
+    #[test]
+    fn every_number_is_a_split() {
+        let mut game_state = create_game_state(1, (80, 24));
+        game_state.mode = Mode::TimeAttack;
+        for (clock, number) in [(12, 1), (30, 2)] {
+            game_state.clock = clock;
+            assert_eq!(game_state.current_number, number);
+            assert!(!TimeAttack.food_eaten(&mut game_state));
+        }
+        assert_eq!(game_state.splits, [12, 30]);
+        assert_eq!(split_delta(30, 42), "-1.2");
+        assert_eq!(split_delta(30, 25), "+0.5");
+        assert_eq!(split_delta(30, 30), "+0.0");
+    }
+}
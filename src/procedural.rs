@@ -0,0 +1,590 @@
+//! Generated levels. Endless games get a fresh layout of wall bars for every
+//! stage, a little denser than the one before, and `--generate` writes
+//! layouts from any of the algorithms here to level files, which `--level`
+//! plays.
+//!
+//! A layout is only kept if it's playable: the free cells all hang together,
+//! so every number can be reached, and the middle of the board, where
+//! `spawn_centred` puts the snake, is clear for a while in every direction.
+
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::leaderboard::{MAX_SIZE, MIN_SIZE};
+use crate::levels::draw_line;
+use crate::{Board, GameRng, board_size};
+
+/// How far the clear area around the spawn reaches to each side.
+const SPAWN_CLEARANCE: (usize, usize) = (6, 4);
+/// How many bars or rooms a layout tries on the board of an 80x24 terminal
+/// before it settles for the walls it got. Larger boards get more.
+const ATTEMPTS: usize = 400;
+/// How many seeds a layout gets before one that loses cells to
+/// `make_playable` will do.
+const RETRIES: u64 = 10;
+
+/// The ways there are to lay out a level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    /// Straight bars in random spots, as in endless games.
+    Scatter,
+    /// Bars from the top and the bottom, the same on both halves.
+    Bars,
+    /// Rooms joined by corridors two cells wide.
+    Rooms,
+    /// Caves grown by a cellular automaton.
+    Caves,
+    /// A maze from recursive division, with corridors wider than the snake
+    /// needs.
+    Maze
+}
+
+pub(crate) const ALGORITHMS: [Algorithm; 5] = [Algorithm::Scatter, Algorithm::Bars, Algorithm::Rooms, Algorithm::Caves, Algorithm::Maze];
+
+impl FromStr for Algorithm {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Algorithm, ()> {
+        ALGORITHMS.into_iter().find(|algorithm| algorithm.to_string() == name).ok_or(())
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::Scatter => "scatter",
+            Algorithm::Bars => "bars",
+            Algorithm::Rooms => "rooms",
+            Algorithm::Caves => "caves",
+            Algorithm::Maze => "maze"
+        })
+    }
+}
+
+/// The share of the board that's wall on `stage`, going up to 30%.
+pub(crate) fn density(stage: u8) -> f32 {
+    (0.03 + 0.02 * (stage as f32 - 1.0)).min(0.3)
+}
+
+/// The generator for the layout of `stage` in a game from `seed`, so that a
+/// replay gets the same walls.
+fn stage_rng(seed: u64, stage: u8) -> GameRng {
+    GameRng::seed_from_u64(seed ^ ((stage as u64) << 56) ^ 0x5eed)
+}
+
+/// Draws the walls of `stage` onto the empty `board`.
+pub(crate) fn render_stage(board: &mut Board, seed: u64, stage: u8) {
+    scatter(board, &mut stage_rng(seed, stage), density(stage));
+}
+
+/// Lays out the empty `board` with `algorithm`, walls on about `density` of
+/// it. The same seed gives the same layout on a board of the same size.
+pub(crate) fn generate(algorithm: Algorithm, board: &mut Board, seed: u64, density: f32) {
+    for attempt in 0..RETRIES {
+        let mut candidate = board.clone();
+        let rng = &mut GameRng::seed_from_u64(seed.wrapping_add(attempt));
+        match algorithm {
+            Algorithm::Scatter => scatter(&mut candidate, rng, density),
+            Algorithm::Bars => bars(&mut candidate, rng, density),
+            Algorithm::Rooms => rooms(&mut candidate, rng, density),
+            Algorithm::Caves => caves(&mut candidate, rng, density),
+            Algorithm::Maze => maze(&mut candidate, rng, density)
+        }
+        // a cave shut off from the middle is a lot of wall that was meant
+        // to be free
+        let lost = make_playable(&mut candidate);
+        if lost * 4 <= free_cells(&candidate) || attempt + 1 == RETRIES {
+            *board = candidate;
+            return;
+        }
+    }
+}
+
+/// Adds straight bars in random spots until `density` of the playable
+/// `board` is wall, leaving out every bar that would make it unplayable.
+pub(crate) fn scatter(board: &mut Board, rng: &mut GameRng, density: f32) {
+    let Board { width, height, .. } = *board;
+    let target = (free_cells(board) as f32 * density) as usize;
+    let mut walls = 0;
+
+    for _ in 0..attempts(board) {
+        if walls >= target {
+            break;
+        }
+        let x = rng.gen_range(1..width);
+        let y = rng.gen_range(1..=height);
+        // rows are half as high as columns are wide
+        let cells: Vec<(usize, usize)> = if rng.gen() {
+            let length = rng.gen_range(2..=(width / 4).max(2));
+            (x..(x + length).min(width)).map(|x| (x, y)).collect()
+        } else {
+            let length = rng.gen_range(2..=(height / 4).max(2));
+            (y..(y + length).min(height + 1)).map(|y| (x, y)).collect()
+        };
+
+        if spawn_area(board).any(|cell| cells.contains(&cell)) {
+            continue;
+        }
+        let before: Vec<i16> = cells.iter().map(|&(x, y)| board.lookup(x, y)).collect();
+        for &(x, y) in &cells {
+            board.set_pixel(x, y, 9);
+        }
+        if cuts_nothing_off(board, &cells) {
+            walls += before.iter().filter(|&&cell| cell < 0).count();
+        } else {
+            for (&(x, y), &cell) in cells.iter().zip(&before) {
+                board.set_pixel(x, y, cell);
+            }
+        }
+    }
+}
+
+/// Evenly spaced bars, every other one hanging from the top and the rest
+/// standing on the bottom like in `render_l8`, mirrored on the right half
+/// like in `render_l6`.
+fn bars(board: &mut Board, rng: &mut GameRng, density: f32) {
+    let Board { width, height, .. } = *board;
+    let shortest = height / 3;
+    // at least two rows stay free under and over every bar
+    let longest = (height - (height / 6).max(2)).max(shortest + 1);
+
+    let walls = free_cells(board) as f32 * density;
+    let pairs = (walls / (shortest + longest) as f32).round().max(1.0) as usize;
+    // bars closer than four columns leave no room to turn
+    let pairs = pairs.min((width / 8).saturating_sub(1)).max(1);
+    let spacing = width / 2 / (pairs + 1);
+
+    for index in 0..pairs {
+        let length = rng.gen_range(shortest..longest);
+        let x = spacing * (index + 1);
+        for x in [x, width - x] {
+            if index % 2 == 0 {
+                draw_line(board, x, 1, x, length);
+            } else {
+                draw_line(board, x, height + 1 - length, x, height);
+            }
+        }
+    }
+}
+
+/// Walls everywhere but in rooms, each joined to the one before. The first
+/// room is around the spawn, so all of them can be reached from there.
+fn rooms(board: &mut Board, rng: &mut GameRng, density: f32) {
+    let Board { width, height, .. } = *board;
+    let target = (free_cells(board) as f32 * (1.0 - density)) as usize;
+    let attempts = attempts(board);
+    fill(board);
+
+    let (reach_x, reach_y) = SPAWN_CLEARANCE;
+    let (mut x, mut y) = (width / 2, height / 2);
+    carve(board, x.saturating_sub(reach_x), y.saturating_sub(reach_y), x + reach_x, y + reach_y);
+
+    for _ in 0..attempts {
+        if free_cells(board) >= target {
+            break;
+        }
+        let (room_width, room_height) = (rng.gen_range(6..=(width / 4).max(6)), rng.gen_range(4..=(height / 4).max(4)));
+        let room_x = rng.gen_range(1..width.saturating_sub(room_width).max(2));
+        let room_y = rng.gen_range(1..(height + 1).saturating_sub(room_height).max(2));
+        carve(board, room_x, room_y, room_x + room_width - 1, room_y + room_height - 1);
+
+        // along, then up or down to the middle of the new room
+        let (next_x, next_y) = (room_x + room_width / 2, room_y + room_height / 2);
+        carve(board, x.min(next_x), y, x.max(next_x), y + 1);
+        carve(board, next_x, y.min(next_y), next_x + 1, y.max(next_y));
+        (x, y) = (next_x, next_y);
+    }
+}
+
+/// Random walls on about `density` of the board, smoothed into caves by
+/// a few rounds of a cellular automaton: a cell is wall if most of its
+/// neighbours are.
+fn caves(board: &mut Board, rng: &mut GameRng, density: f32) {
+    let Board { width, height, .. } = *board;
+    // the smoothing wears sparse walls away, so they start out denser
+    let chance = (density as f64 + 0.15).min(0.6);
+    let mut walls = vec![vec![false; width]; height + 1];
+    for (y, row) in walls.iter_mut().enumerate().skip(1) {
+        for (x, wall) in row.iter_mut().enumerate().skip(1) {
+            *wall = board.lookup(x, y) >= 0 || rng.gen_bool(chance);
+        }
+    }
+
+    for _ in 0..4 {
+        let previous = walls.clone();
+        for (y, row) in walls.iter_mut().enumerate().skip(1) {
+            for (x, wall) in row.iter_mut().enumerate().skip(1) {
+                let neighbours = (y - 1..=y + 1)
+                    .flat_map(|ny| (x - 1..=x + 1).map(move |nx| (nx, ny)))
+                    .filter(|&(nx, ny)| (nx, ny) != (x, y) && previous.get(ny).and_then(|row| row.get(nx)) == Some(&true))
+                    .count();
+                *wall = neighbours >= 5 || (*wall && neighbours >= 4);
+            }
+        }
+    }
+
+    for (y, row) in walls.iter().enumerate().skip(1) {
+        for (x, &wall) in row.iter().enumerate().skip(1) {
+            if wall {
+                board.set_pixel(x, y, 9);
+            }
+        }
+    }
+}
+
+/// Splits the board with a wall that has a gap in it, then the two halves
+/// the same way, until the chambers are too small to split. The denser the
+/// maze, the narrower its corridors.
+fn maze(board: &mut Board, rng: &mut GameRng, density: f32) {
+    let corridor = 2 + ((1.0 - density.clamp(0.0, 1.0)) * 6.0) as usize;
+    divide(board, rng, (1, 1, board.width - 1, board.height), corridor);
+}
+
+/// Splits the chamber from `(x0, y0)` to `(x1, y1)`, both inclusive, see
+/// `maze`.
+fn divide(board: &mut Board, rng: &mut GameRng, (x0, y0, x1, y1): (usize, usize, usize, usize), corridor: usize) {
+    let (width, height) = (x1 + 1 - x0, y1 + 1 - y0);
+    // rows are half as high as columns are wide
+    let vertical = width * 2 >= height;
+    let (length, across) = if vertical { (width, height) } else { (height, width) };
+    if length < 2 * corridor + 1 || across <= corridor {
+        return;
+    }
+
+    // a wall right in front of a gap in the wall around the chamber would
+    // close it
+    let is_gap = |x: usize, y: usize| x >= 1 && y >= 1 && x < board.width && y <= board.height && board.lookup(x, y) < 0;
+    let spots: Vec<usize> = if vertical {
+        (x0 + corridor..=x1 - corridor).filter(|&x| !is_gap(x, y0 - 1) && !is_gap(x, y1 + 1)).collect()
+    } else {
+        (y0 + corridor..=y1 - corridor).filter(|&y| !is_gap(x0 - 1, y) && !is_gap(x1 + 1, y)).collect()
+    };
+    if spots.is_empty() {
+        return;
+    }
+    let spot = spots[rng.gen_range(0..spots.len())];
+    let gap = rng.gen_range(0..=across - corridor);
+
+    if vertical {
+        draw_line(board, spot, y0, spot, y1);
+        for y in y0 + gap..y0 + gap + corridor {
+            board.set_pixel(spot, y, -1);
+        }
+        divide(board, rng, (x0, y0, spot - 1, y1), corridor);
+        divide(board, rng, (spot + 1, y0, x1, y1), corridor);
+    } else {
+        draw_line(board, x0, spot, x1, spot);
+        for x in x0 + gap..x0 + gap + corridor {
+            board.set_pixel(x, spot, -1);
+        }
+        divide(board, rng, (x0, y0, x1, spot - 1), corridor);
+        divide(board, rng, (x0, spot + 1, x1, y1), corridor);
+    }
+}
+
+/// Clears the spawn area and walls in whatever can't be reached from it.
+/// Returns how many free cells were walled in.
+fn make_playable(board: &mut Board) -> usize {
+    for (x, y) in spawn_area(board) {
+        board.set_pixel(x, y, -1);
+    }
+    let reached = reached(board, (board.width / 2, board.height / 2));
+    let mut lost = 0;
+    for (y, row) in reached.iter().enumerate().skip(1) {
+        for (x, &reached) in row.iter().enumerate().skip(1) {
+            if !reached && board.lookup(x, y) < 0 {
+                board.set_pixel(x, y, 9);
+                lost += 1;
+            }
+        }
+    }
+    lost
+}
+
+/// Whether the spawn area is clear and every free cell can be reached from it.
+pub(crate) fn is_playable(board: &Board) -> bool {
+    let reachable: usize = reached(board, (board.width / 2, board.height / 2)).iter()
+        .map(|row| row.iter().filter(|&&reached| reached).count())
+        .sum();
+    spawn_area(board).all(|(x, y)| board.lookup(x, y) < 0) && reachable == free_cells(board)
+}
+
+/// A level as text, a line per row from the top: `#` for wall, `.` for free.
+pub(crate) fn level_text(board: &Board) -> String {
+    (1..=board.height)
+        .map(|y| (1..board.width).map(|x| if board.lookup(x, y) < 0 { '.' } else { '#' }).collect::<String>() + "\n")
+        .collect()
+}
+
+/// Reads a level written by `level_text` back into a board. The level has to
+/// be the size of the board of a terminal the leaderboard takes, and
+/// playable.
+pub(crate) fn parse_level(text: &str) -> Result<Board, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let width = lines.first().map_or(0, |line| line.chars().count()) + 1;
+    let height = lines.len();
+    let (smallest, largest) = (board_size(MIN_SIZE), board_size(MAX_SIZE));
+    if !(smallest.0..=largest.0).contains(&width) || !(smallest.1..=largest.1).contains(&height) {
+        return Err(format!("a level is {}x{} to {}x{} cells, not {}x{height}", smallest.0 - 1, smallest.1, largest.0 - 1, largest.1, width - 1));
+    }
+
+    let mut board = Board::new(width, height);
+    for (y, line) in lines.iter().enumerate() {
+        if line.chars().count() != width - 1 {
+            return Err(format!("line {} isn't {} cells wide", y + 1, width - 1));
+        }
+        for (x, cell) in line.chars().enumerate() {
+            match cell {
+                '#' => board.set_pixel(x + 1, y + 1, 9),
+                '.' => (),
+                _ => return Err(format!("line {} has a {cell:?}, levels are made of '#' and '.'", y + 1))
+            }
+        }
+    }
+    if !is_playable(&board) {
+        return Err("the level has free cells that can't be reached, or walls around the middle".to_string());
+    }
+    Ok(board)
+}
+
+/// Puts the walls of `level` in the middle of the empty `board`, cutting off
+/// what doesn't fit, and makes the result playable. A board the size of
+/// `level` ends up the same as it.
+pub(crate) fn lay_out(board: &mut Board, level: &Board) {
+    // the middles line up, so the spawn areas do too
+    let offset = |size: usize, level: usize| (size / 2) as isize - (level / 2) as isize;
+    let (offset_x, offset_y) = (offset(board.width, level.width), offset(board.height, level.height));
+    for y in 1..=level.height {
+        for x in 1..level.width {
+            let (to_x, to_y) = (x as isize + offset_x, y as isize + offset_y);
+            if level.lookup(x, y) >= 0 && to_x >= 1 && to_y >= 1 && to_x < board.width as isize && to_y <= board.height as isize {
+                board.set_pixel(to_x as usize, to_y as usize, 9);
+            }
+        }
+    }
+    make_playable(board);
+}
+
+/// The cells around the middle of the board that have to stay free.
+fn spawn_area(board: &Board) -> impl Iterator<Item = (usize, usize)> {
+    let Board { width, height, .. } = *board;
+    let (center_x, center_y) = (width / 2, height / 2);
+    let (reach_x, reach_y) = SPAWN_CLEARANCE;
+    (center_x.saturating_sub(reach_x).max(1)..=(center_x + reach_x).min(width - 1))
+        .flat_map(move |x| (center_y.saturating_sub(reach_y).max(1)..=(center_y + reach_y).min(height)).map(move |y| (x, y)))
+}
+
+/// Whether every free cell is still in reach of the others now that `cells`
+/// are wall, as long as that was so before. It is if the free cells around
+/// them, diagonals included, reach each other, since a way through `cells`
+/// can go round them instead. A search from each of those takes a step in
+/// turn, and ones that meet go on as one, so a pocket that's cut off is found
+/// out about as soon as it's searched, not once the rest of the board is.
+fn cuts_nothing_off(board: &Board, cells: &[(usize, usize)]) -> bool {
+    let index = |(x, y): (usize, usize)| y * board.width + x;
+    let free = |(x, y): (usize, usize)| x >= 1 && y >= 1 && x < board.width && y <= board.height && board.lookup(x, y) < 0;
+
+    let mut searches: Vec<VecDeque<(usize, usize)>> = Vec::new();
+    // which search got to a cell first, if any did
+    let mut owners = vec![usize::MAX; board.width * (board.height + 1)];
+    for &(x, y) in cells {
+        for cell in (x - 1..=x + 1).flat_map(|nx| (y - 1..=y + 1).map(move |ny| (nx, ny))) {
+            if free(cell) && owners[index(cell)] == usize::MAX {
+                owners[index(cell)] = searches.len();
+                searches.push(VecDeque::from([cell]));
+            }
+        }
+    }
+    // the search each one went on as
+    let mut merged: Vec<usize> = (0..searches.len()).collect();
+    let mut left = searches.len();
+
+    while left > 1 {
+        for search in 0..searches.len() {
+            if merged[search] != search {
+                continue;
+            }
+            let Some((x, y)) = searches[search].pop_front() else {
+                return false;
+            };
+            for next in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                if !free(next) {
+                    continue;
+                }
+                let mut owner = owners[index(next)];
+                if owner == usize::MAX {
+                    owners[index(next)] = search;
+                    searches[search].push_back(next);
+                    continue;
+                }
+                while merged[owner] != owner {
+                    owner = merged[owner];
+                }
+                if owner != search {
+                    merged[owner] = search;
+                    let queue = std::mem::take(&mut searches[owner]);
+                    searches[search].extend(queue);
+                    left -= 1;
+                    if left == 1 {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+    true
+}
+
+fn free_cells(board: &Board) -> usize {
+    (1..=board.height).map(|y| (1..board.width).filter(|&x| board.lookup(x, y) < 0).count()).sum()
+}
+
+/// How many bars or rooms to try on `board`, so larger ones get as dense.
+fn attempts(board: &Board) -> usize {
+    let (width, height) = board_size((80, 24));
+    ATTEMPTS.max(ATTEMPTS * free_cells(board) / ((width - 1) * height))
+}
+
+/// Walls in the whole board.
+fn fill(board: &mut Board) {
+    for y in 1..=board.height {
+        draw_line(board, 1, y, board.width - 1, y);
+    }
+}
+
+/// Frees the cells from `(x0, y0)` to `(x1, y1)`, both inclusive, as far as
+/// they are on the board.
+fn carve(board: &mut Board, x0: usize, y0: usize, x1: usize, y1: usize) {
+    for y in y0.max(1)..=y1.min(board.height) {
+        for x in x0.max(1)..=x1.min(board.width - 1) {
+            board.set_pixel(x, y, -1);
+        }
+    }
+}
+
+/// Which free cells there are in reach of `start`, itself included.
+fn reached(board: &Board, start: (usize, usize)) -> Vec<Vec<bool>> {
+    let mut seen = vec![vec![false; board.width]; board.height + 1];
+    if board.lookup(start.0, start.1) >= 0 {
+        return seen;
+    }
+    let mut stack = vec![start];
+    seen[start.1][start.0] = true;
+
+    while let Some((x, y)) = stack.pop() {
+        for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+            if nx >= 1 && ny >= 1 && nx < board.width && ny <= board.height
+                && !seen[ny][nx] && board.lookup(nx, ny) < 0 {
+                seen[ny][nx] = true;
+                stack.push((nx, ny));
+            }
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_get_denser_and_stay_playable() {
+        let mut previous = 0;
+        for stage in [1, 5, 10, 20] {
+            let mut board = Board::new(78, 44);
+            render_stage(&mut board, 11, stage);
+            assert!(is_playable(&board));
+            let walls = 78 * 44 - free_cells(&board);
+            assert!(walls > previous, "stage {stage} has {walls} walls");
+            previous = walls;
+        }
+    }
+
+    #[test]
+    fn large_boards_get_as_dense() {
+        let (width, height) = board_size((300, 100));
+        let mut board = Board::new(width, height);
+        let cells = free_cells(&board);
+        render_stage(&mut board, 11, 20);
+        let walls = cells - free_cells(&board);
+        assert!(walls as f32 >= cells as f32 * density(20) * 0.9, "{walls} walls in {cells} cells");
+    }
+
+    #[test]
+    fn cut_off_areas_are_caught() {
+        let mut board = Board::new(40, 20);
+        assert!(is_playable(&board));
+        for y in 1..=20 {
+            board.set_pixel(5, y, 9);
+        }
+        assert!(!is_playable(&board));
+
+        let mut board = Board::new(40, 20);
+        board.set_pixel(20, 10, 9);
+        assert!(!is_playable(&board), "the spawn is walled in");
+    }
+
+    #[test]
+    fn every_algorithm_makes_playable_levels() {
+        for algorithm in ALGORITHMS {
+            for (size, density) in [((79, 44), 0.2), ((160, 90), 0.4), ((39, 28), 0.6)] {
+                let mut board = Board::new(size.0, size.1);
+                generate(algorithm, &mut board, 5, density);
+                assert!(is_playable(&board), "{algorithm} on {size:?}");
+                let walls = size.0 * size.1 - free_cells(&board);
+                assert!(walls > size.1, "{algorithm} on {size:?} has walls");
+
+                let mut again = Board::new(size.0, size.1);
+                generate(algorithm, &mut again, 5, density);
+                assert_eq!(level_text(&again), level_text(&board));
+            }
+        }
+        assert_eq!("maze".parse(), Ok(Algorithm::Maze));
+    }
+
+    #[test]
+    fn level_files_read_back_the_same() {
+        let mut board = Board::new(79, 44);
+        generate(Algorithm::Rooms, &mut board, 8, 0.3);
+        let text = level_text(&board);
+        let level = parse_level(&text).unwrap();
+        assert_eq!(level_text(&level), text);
+
+        let mut same = Board::new(79, 44);
+        lay_out(&mut same, &level);
+        assert_eq!(level_text(&same), text);
+        for (width, height) in [(119, 76), (39, 28)] {
+            let mut other = Board::new(width, height);
+            lay_out(&mut other, &level);
+            assert!(is_playable(&other), "{width}x{height}");
+        }
+    }
+
+    #[test]
+    fn broken_level_files_are_rejected() {
+        let text = level_text(&Board::new(79, 44));
+        assert!(parse_level(&text).is_ok());
+        assert!(parse_level("").is_err());
+        assert!(parse_level(&level_text(&Board::new(20, 10))).is_err(), "too small");
+        assert!(parse_level(&text.replacen('.', "x", 1)).is_err());
+        assert!(parse_level(&text.replacen(".\n", "\n", 1)).is_err(), "ragged");
+
+        let mut walled_in = Board::new(79, 44);
+        for y in 1..=44 {
+            walled_in.set_pixel(5, y, 9);
+        }
+        assert!(parse_level(&level_text(&walled_in)).is_err());
+    }
+
+    #[test]
+    fn bars_are_the_same_on_both_halves() {
+        let mut board = Board::new(80, 44);
+        generate(Algorithm::Bars, &mut board, 3, 0.3);
+        for line in level_text(&board).lines() {
+            // the mirror of column x is column 80 - x
+            assert_eq!(line.chars().rev().collect::<String>(), line);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Split-screen race: two players in one terminal, each on their own copy of
+//! the same level from the same seed, side by side. Numbers turn up in the
+//! same spots for both, so neither gets luckier food than the other.
+//!
+//! The first to eat number 10 wins, and so does the last one with lives
+//! left. The left player steers with WASD and goes on with SPACE, the right
+//! one with the arrow keys and ENTER.
+
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::backend::Backend;
+use crate::events::{EventLoop, TerminalInput};
+use crate::guard::{SignalAction, TerminalGuard};
+use crate::render::{self, Renderer};
+use crate::terminal::{TermColor, Terminal};
+use crate::{Direction, GameEvent, GameRng, GameState, UPDATE_TIME, advance, create_game_state, game_over, shown_board};
+
+/// A key of the right player.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum Key {
+    Up,
+    Right,
+    Down,
+    Left,
+    Action
+}
+
+impl Key {
+    /// Where the key steers, unless it's the action key.
+    pub(crate) fn direction(self) -> Option<Direction> {
+        match self {
+            Key::Up => Some(Direction::Up),
+            Key::Right => Some(Direction::Right),
+            Key::Down => Some(Direction::Down),
+            Key::Left => Some(Direction::Left),
+            Key::Action => None
+        }
+    }
+
+    fn event(self) -> GameEvent {
+        match self {
+            Key::Up => GameEvent::Up,
+            Key::Right => GameEvent::Right,
+            Key::Down => GameEvent::Down,
+            Key::Left => GameEvent::Left,
+            Key::Action => GameEvent::Action
+        }
+    }
+}
+
+/// How a race ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Won(usize),
+    Draw
+}
+
+/// The generator for where number `number` goes on `level` in a race with
+/// numbers from `seed`, the same for both players however their games went.
+pub(crate) fn number_rng(seed: u64, level: u8, number: u8) -> GameRng {
+    GameRng::seed_from_u64(seed ^ ((level as u64) << 56) ^ ((number as u64) << 48))
+}
+
+/// Keys for two players: the second one gets the arrows and ENTER,
+/// everything else means what it always does.
+pub(crate) fn translate_event(event: Event) -> Option<GameEvent> {
+    match event {
+        Event::Key(key) if !key.modifiers.contains(KeyModifiers::CONTROL) => match key.code {
+            KeyCode::Up => Some(GameEvent::Second(Key::Up)),
+            KeyCode::Right => Some(GameEvent::Second(Key::Right)),
+            KeyCode::Down => Some(GameEvent::Second(Key::Down)),
+            KeyCode::Left => Some(GameEvent::Second(Key::Left)),
+            KeyCode::Enter => Some(GameEvent::Second(Key::Action)),
+            _ => crate::translate_event(event)
+        }
+        _ => crate::translate_event(event)
+    }
+}
+
+pub(crate) struct Race {
+    players: [GameState; 2],
+    renderers: [Renderer; 2],
+    frame: Terminal,
+    terminal_size: (u16, u16),
+    /// Whether the clocks are running. Both games start together.
+    started: bool,
+    outcome: Option<Outcome>
+}
+
+impl Race {
+    pub(crate) fn new(seed: u64, terminal_size: (u16, u16)) -> Race {
+        let player = || {
+            let mut game_state = create_game_state(seed, half(terminal_size));
+            game_state.race_seed = Some(seed);
+            // there's no room for it next to the score on half a screen
+            game_state.show_frame_time = false;
+            game_state
+        };
+        Race {
+            players: [player(), player()],
+            renderers: [Renderer::new(), Renderer::new().action_key("ENTER")],
+            frame: Terminal::new(terminal_size.0 as usize, terminal_size.1 as usize),
+            terminal_size,
+            started: false,
+            outcome: None
+        }
+    }
+
+    pub(crate) fn handle(&mut self, event: GameEvent) {
+        match event {
+            GameEvent::Resize(width, height) => {
+                self.terminal_size = (width, height);
+                self.frame = Terminal::new(width as usize, height as usize);
+                let (width, height) = half(self.terminal_size);
+                for player in self.players.iter_mut() {
+                    advance(GameEvent::Resize(width, height), player);
+                }
+            }
+            GameEvent::Action | GameEvent::Second(Key::Action) if self.outcome.is_some() => {
+                *self = Race::new(rand::random(), self.terminal_size);
+            }
+            GameEvent::Action | GameEvent::Second(Key::Action) if !self.started => {
+                self.started = true;
+                for player in self.players.iter_mut() {
+                    advance(GameEvent::Action, player);
+                }
+            }
+            GameEvent::Escape | GameEvent::Suspend => self.pause(),
+            GameEvent::Second(key) => {
+                advance(key.event(), &mut self.players[1]);
+            }
+            event => {
+                advance(event, &mut self.players[0]);
+            }
+        }
+    }
+
+    /// Stops both games, each player goes on with their own key.
+    pub(crate) fn pause(&mut self) {
+        for player in self.players.iter_mut() {
+            advance(GameEvent::Escape, player);
+        }
+    }
+
+    /// Moves both games on by a tick, until one of the players won.
+    pub(crate) fn tick(&mut self) {
+        if self.outcome.is_some() || !self.started {
+            return;
+        }
+        for player in self.players.iter_mut() {
+            advance(GameEvent::Timeout, player);
+        }
+        // finishing the level moves a player on to the next
+        let finished = self.players.each_ref().map(|player| player.level > 1);
+        let out = self.players.each_ref().map(game_over);
+        self.outcome = match (finished, out) {
+            ([true, true], _) | (_, [true, true]) => Some(Outcome::Draw),
+            ([true, false], _) | (_, [false, true]) => Some(Outcome::Won(0)),
+            ([false, true], _) | (_, [true, false]) => Some(Outcome::Won(1)),
+            _ => None
+        };
+    }
+
+    /// Moves the animations of both halves, see `Renderer::animate`.
+    pub(crate) fn animate(&mut self, elapsed: Duration) -> (bool, Option<Duration>) {
+        let [(left, next), (right, _)] = [0, 1].map(|index| self.renderers[index].animate(&self.players[index], elapsed));
+        (left || right, next)
+    }
+
+    /// Draws both games next to each other, and who won once someone did.
+    pub(crate) fn render(&mut self) -> &Terminal {
+        let width = self.terminal_size.0 as usize;
+        self.frame.reset();
+        for (index, (player, renderer)) in self.players.iter_mut().zip(self.renderers.iter_mut()).enumerate() {
+            let board = shown_board(player);
+            let half = renderer.render(player, &board);
+            // an odd column is left over in the middle
+            let x = if index == 0 { 0 } else { width - half.size.0 };
+            self.frame.paste(half, x, 0);
+        }
+        if let Some(outcome) = self.outcome {
+            let message = match outcome {
+                Outcome::Won(0) => "Left player wins! SPACE or ENTER for another race",
+                Outcome::Won(_) => "Right player wins! SPACE or ENTER for another race",
+                Outcome::Draw => "It's a draw! SPACE or ENTER for another race"
+            };
+            render::draw_box(&mut self.frame, message, TermColor::Color(15));
+        }
+        &self.frame
+    }
+}
+
+/// The terminal size each player's game is laid out for.
+fn half(terminal_size: (u16, u16)) -> (u16, u16) {
+    (terminal_size.0 / 2, terminal_size.1)
+}
+
+/// Runs races on this terminal until someone quits.
+pub(crate) fn run(guard: &TerminalGuard, backend: &mut dyn Backend) {
+    let mut event_loop = EventLoop::new(UPDATE_TIME).unwrap();
+    event_loop.attach(Box::new(TerminalInput::new().unwrap().keys(translate_event))).unwrap();
+    let mut race = Race::new(rand::random(), crossterm::terminal::size().unwrap());
+    let mut inputs = Vec::new();
+    let started = Instant::now();
+    let mut dirty = true;
+    let mut next_frame = started;
+    let mut next_animation = None;
+
+    backend.invalidate().unwrap();
+
+    loop {
+        match guard.check_signals().unwrap() {
+            SignalAction::Quit => return,
+            SignalAction::Resumed => {
+                race.pause();
+                backend.invalidate().unwrap();
+                event_loop.reset_clock().unwrap();
+                dirty = true;
+            }
+            SignalAction::Nothing => ()
+        }
+
+        let deadline = if dirty { Some(next_frame) } else { next_animation };
+        let ticks = event_loop.wait(&mut inputs, deadline).unwrap();
+        for event in inputs.drain(..) {
+            match event {
+                GameEvent::Quit => return,
+                GameEvent::Suspend => {
+                    guard.suspend().unwrap();
+                    race.pause();
+                    backend.invalidate().unwrap();
+                    event_loop.reset_clock().unwrap();
+                }
+                GameEvent::Redraw => backend.invalidate().unwrap(),
+                event => {
+                    if let GameEvent::Resize(..) = event {
+                        backend.invalidate().unwrap();
+                    }
+                    race.handle(event);
+                }
+            }
+            dirty = true;
+        }
+        for _ in 0..ticks {
+            race.tick();
+            dirty = true;
+        }
+
+        let now = Instant::now();
+        let (animated, until_animation) = race.animate(now - started);
+        dirty |= animated;
+        next_animation = until_animation.map(|until| now + until);
+        if dirty && now >= next_frame {
+            backend.draw(race.render()).unwrap();
+            dirty = false;
+            next_frame = now + crate::FRAME_TIME;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_turn_up_in_the_same_spots_for_both() {
+        let mut race = Race::new(7, (120, 30));
+        race.handle(GameEvent::Action);
+        assert_eq!(race.players[0].number_pos, race.players[1].number_pos);
+        let first = race.players[0].number_pos;
+
+        // the right player heads into a wall, the left one keeps going
+        race.handle(GameEvent::Second(Key::Up));
+        while race.players[1].lives == 5 {
+            race.tick();
+        }
+        assert_eq!(race.players[1].current_number, 1);
+        assert_eq!(race.players[1].number_pos, first);
+        assert_eq!(race.outcome, None);
+    }
+
+    #[test]
+    fn the_last_one_with_lives_wins() {
+        let mut race = Race::new(8, (120, 30));
+        race.handle(GameEvent::Action);
+        race.players[0].lives = 1;
+        race.handle(GameEvent::Up);
+        while race.outcome.is_none() {
+            race.handle(GameEvent::Second(Key::Action));
+            race.tick();
+        }
+        assert_eq!(race.outcome, Some(Outcome::Won(1)));
+        assert!(race.render().rows.iter().any(|row| row.iter().map(|cell| cell.data).collect::<String>().contains("Right player wins")));
+
+        race.handle(GameEvent::Action);
+        assert_eq!(race.outcome, None);
+        assert!(!race.started);
+    }
+
+    #[test]
+    fn narrow_terminals_race_without_room_for_the_boxes() {
+        for size in [(50, 24), (20, 8)] {
+            let mut race = Race::new(9, size);
+            race.render();
+            race.handle(GameEvent::Action);
+            race.render();
+            race.outcome = Some(Outcome::Draw);
+            let frame = race.render();
+            assert_eq!(frame.size, (size.0 as usize, size.1 as usize));
+            assert!(frame.rows.iter().any(|row| row.iter().map(|cell| cell.data).collect::<String>().contains("It's a draw!")));
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::terminal::{Cell, Terminal, TermColor};
+use crate::battle;
+use crate::modes;
+use crate::{Board, GameState, SnakeCell, game_over, get_playfield, too_small};
+
+static TEXT: &str = 
+r"
+   ______                        ____                     
+  / ____/___ _____ ___  ___     / __ \_   _____  _____    
+ / / __/ __ `/ __ `__ \/ _ \   / / / / | / / _ \/ ___/    
+/ /_/ / /_/ / / / / / /  __/  / /_/ /| |/ /  __/ /        
+\____/\____/_/ /_/ /_/\___/   \____/ |___/\___/_/         
+";
+
+const SNAKE_COLOR: i16 = 11;
+
+/// The colours the "Press SPACE" prompts pulse through, one per
+/// `PULSE_STEP`, starting from plain white.
+const PULSE: &[u8] = &[15, 255, 254, 253, 252, 251, 250, 249, 248, 249, 250, 251, 252, 253, 254, 255];
+const PULSE_STEP: Duration = Duration::from_millis(75);
+
+/// Turns the game into frames without allocating on every tick.
+///
+/// The border and the walls of a level only change with the level or the
+/// terminal size, so they are drawn once into a static layer. Every frame
+/// starts out as a copy of that layer, with the snake, the number and the HUD
+/// drawn on top of it.
+///
+/// UI animations run on their own clock rather than the simulation's, see
+/// `animate`.
+pub(crate) struct Renderer {
+    /// The board and terminal size the static layer was drawn for.
+    key: Option<(Rc<Board>, (u16, u16))>,
+    static_layer: Terminal,
+    frame: Terminal,
+    /// The snakes' pixels for the current frame, so a cell shared by two
+    /// pixels can tell whether its other half is snake as well.
+    overlay: Board,
+    animated: bool,
+    /// Where the prompts are in `PULSE`.
+    pulse: usize,
+    /// The table listed on the game over screen, if there is one.
+    high_scores: Vec<String>,
+    /// A line for the game over screen, below the prompt.
+    game_over_note: Option<String>,
+    /// The key the pause box asks for.
+    action_key: &'static str,
+    /// A line under the pause box, for choices before the game starts.
+    menu: Option<String>,
+    /// The splits to hold the game's against, see `modes::split_delta`.
+    best_splits: Vec<u64>
+}
+
+impl Renderer {
+    pub(crate) fn new() -> Renderer {
+        Renderer {
+            key: None,
+            static_layer: Terminal::new(0, 0),
+            frame: Terminal::new(0, 0),
+            overlay: Board::new(0, 0),
+            animated: true,
+            pulse: 0,
+            high_scores: Vec::new(),
+            game_over_note: None,
+            action_key: "SPACE",
+            menu: None,
+            best_splits: Vec::new()
+        }
+    }
+
+    pub(crate) fn animated(mut self, animated: bool) -> Renderer {
+        self.animated = animated;
+        self
+    }
+
+    pub(crate) fn action_key(mut self, key: &'static str) -> Renderer {
+        self.action_key = key;
+        self
+    }
+
+    pub(crate) fn set_menu(&mut self, menu: Option<String>) {
+        self.menu = menu;
+    }
+
+    pub(crate) fn set_best_splits(&mut self, splits: Vec<u64>) {
+        self.best_splits = splits;
+    }
+
+    /// Lists `lines` under the game over text from now on, best first.
+    pub(crate) fn set_high_scores(&mut self, lines: Vec<String>) {
+        self.high_scores = lines;
+    }
+
+    pub(crate) fn set_game_over_note(&mut self, note: Option<String>) {
+        self.game_over_note = note;
+    }
+
+    /// Moves the animations to `elapsed` into the game. Returns whether that
+    /// changed what the next frame looks like, and when it will change next.
+    pub(crate) fn animate(&mut self, game_state: &GameState, elapsed: Duration) -> (bool, Option<Duration>) {
+        if !self.animated || !(game_state.paused || game_state.demo || game_over(game_state)) {
+            return (false, None);
+        }
+        let step = PULSE_STEP.as_nanos();
+        let pulse = (elapsed.as_nanos() / step) as usize % PULSE.len();
+        let next = Duration::from_nanos((step - elapsed.as_nanos() % step) as u64);
+        (std::mem::replace(&mut self.pulse, pulse) != pulse, Some(next))
+    }
+
+    /// Draws the frame for `game_state` on `board`, sized to the game's
+    /// terminal size.
+    pub(crate) fn render(&mut self, game_state: &GameState, board: &Rc<Board>) -> &Terminal {
+        let size = game_state.terminal_size;
+        let prompt_color = TermColor::Color(PULSE[self.pulse]);
+        if too_small(size) {
+            if self.frame.size != (size.0 as usize, size.1 as usize) {
+                self.frame = Terminal::new(size.0 as usize, size.1 as usize);
+            }
+            self.frame.reset();
+            draw_too_small(&mut self.frame);
+            return &self.frame;
+        }
+        let up_to_date = matches!(&self.key, Some((key_board, key_size)) if Rc::ptr_eq(key_board, board) && *key_size == size);
+        if !up_to_date {
+            self.static_layer = Terminal::new(size.0 as usize, size.1 as usize);
+            draw_static(board, size, &mut self.static_layer);
+            self.overlay = Board::new(board.width, board.height);
+            self.key = Some((Rc::clone(board), size));
+        }
+
+        if game_over(game_state) {
+            if self.frame.size != self.static_layer.size {
+                self.frame = Terminal::new(size.0 as usize, size.1 as usize);
+            }
+            self.frame.reset();
+            draw_game_over(&mut self.frame, prompt_color, self.game_over_note.as_deref(), &self.high_scores);
+            return &self.frame;
+        }
+
+        self.frame.copy_from(&self.static_layer);
+        draw_snakes(game_state, board, &mut self.overlay, &mut self.frame);
+        draw_dynamic(game_state, &mut self.frame, prompt_color, self.action_key, self.menu.as_deref(), &self.best_splits);
+        &self.frame
+    }
+}
+
+fn draw_game_over(buffer: &mut Terminal, prompt_color: TermColor, note: Option<&str>, high_scores: &[String]) {
+    buffer.clear();
+    buffer.set_foreground(TermColor::Color(12)); // ???
+    write!(
+        buffer,
+        "{}",
+        TEXT
+    ).unwrap();
+
+    buffer.set_foreground(prompt_color);
+    write!(
+        buffer,
+        "\nPress SPACE to start again",
+    ).unwrap();
+    if let Some(note) = note {
+        write!(buffer, "\n{note}").unwrap();
+    }
+
+    // below the prompt, as far down as the screen goes
+    let top = TEXT.lines().count() + 4;
+    if high_scores.is_empty() || top + 1 >= buffer.size.1 {
+        return;
+    }
+    buffer.set_foreground(TermColor::Color(15));
+    buffer.goto(0, top);
+    write!(buffer, "High scores").unwrap();
+    for (y, line) in (top + 1..buffer.size.1).zip(high_scores) {
+        buffer.goto(0, y);
+        write!(buffer, "{line}").unwrap();
+    }
+}
+
+/// Draws everything that stays the same for the whole level: the border and
+/// the level's walls.
+fn draw_static(board: &Board, terminal_size: (u16, u16), buffer: &mut Terminal) {
+    let (width, height) = buffer.size;
+
+    buffer.goto(0, 1);
+    buffer.set_foreground(TermColor::Color(9));
+
+    writeln!(buffer, "\u{2588}{}\u{2588}", "\u{2580}".repeat(width - 2)).unwrap();
+    for _ in 3..height {
+        write!(buffer, "\u{2588}").unwrap();
+        buffer.right(width - 2);
+        write!(buffer, "\u{2588}").unwrap();
+        buffer.down(1);
+        buffer.ret();
+    }
+    write!(buffer, "\u{2588}{}\u{2588}", "\u{2584}".repeat(width - 2)).unwrap();
+
+    let playfield = get_playfield(terminal_size);
+    let edge = playfield.bottom as usize / 2;
+
+    for (y, (odd, even)) in board.iter().enumerate() {
+        for (x, &co) in odd.iter().enumerate() {
+            let ce = even.map_or(-1, |even| even[x]);
+            if let Some(cell) = pixel_cell(co, ce, check_bottom_top(edge, y)) {
+                put_cell(buffer, x, y + 1, cell);
+            }
+        }
+    }
+}
+
+/// Draws the snakes' pixels over the static layer. Only the cells the snakes
+/// cover are touched, recomputed from the walls and the snakes' pixels.
+fn draw_snakes(game_state: &GameState, board: &Board, overlay: &mut Board, buffer: &mut Terminal) {
+    let snakes = || std::iter::once((SNAKE_COLOR, &game_state.snake))
+        .chain(battle::COLORS.into_iter().zip(game_state.opponents.iter()).map(|(color, opponent)| (color, &opponent.snake)));
+    for (color, snake) in snakes() {
+        for &SnakeCell(x, y) in snake.iter() {
+            overlay.set_pixel(x as usize, y as usize, color);
+        }
+    }
+
+    let playfield = get_playfield(game_state.terminal_size);
+    let edge = playfield.bottom as usize / 2;
+    let pixel = |x: usize, y: usize| match overlay.rows.get(y) {
+        Some(row) if row[x] >= 0 => row[x],
+        Some(_) => board.lookup(x, y),
+        None => -1
+    };
+
+    for (_, snake) in snakes() {
+        for &SnakeCell(x, y) in snake.iter() {
+            let (x, pair) = (x as usize, y as usize / 2);
+            let (co, ce) = (pixel(x, pair * 2), pixel(x, pair * 2 + 1));
+            if let Some(cell) = pixel_cell(co, ce, check_bottom_top(edge, pair)) {
+                put_cell(buffer, x, pair + 1, cell);
+            }
+        }
+    }
+
+    for (_, snake) in snakes() {
+        for &SnakeCell(x, y) in snake.iter() {
+            overlay.set_pixel(x as usize, y as usize, -1);
+        }
+    }
+}
+
+/// Draws the HUD, the number and the pause or demo box.
+fn draw_dynamic(game_state: &GameState, buffer: &mut Terminal, prompt_color: TermColor, action_key: &str, menu: Option<&str>, best_splits: &[u64]) {
+    let width = buffer.size.0;
+
+    buffer.goto(0, 0);
+    buffer.set_foreground(TermColor::Color(15));
+    buffer.set_background(TermColor::Default);
+
+    let state_len = if !game_state.paused {
+        let hud = game_state.mode.rules().hud(game_state);
+        write!(buffer, "{hud}").unwrap();
+        let mut len = hud.chars().count();
+        // the last split against the best run's, green when ahead
+        let last = game_state.splits.len().checked_sub(1);
+        if let Some((split, &best)) = last.and_then(|index| Some((game_state.splits[index], best_splits.get(index)?))) {
+            let delta = format!("  {}", modes::split_delta(split, best));
+            let color = match split.cmp(&best) {
+                Ordering::Less => 10,
+                Ordering::Equal => 15,
+                Ordering::Greater => 9
+            };
+            buffer.set_foreground(TermColor::Color(color));
+            write!(buffer, "{delta}").unwrap();
+            buffer.set_foreground(TermColor::Color(15));
+            len += delta.len();
+        }
+        len
+    } else {
+        static PAUSED_TEXT: &str = "    Paused";
+        write!(buffer, "{}", PAUSED_TEXT).unwrap();
+        PAUSED_TEXT.len()
+    };
+    let score_str = game_state.score.to_string();
+    // the opponents' scores go left of the player's, each in its snake's
+    // colour, or grey once it's out of the round
+    let opponent_scores: Vec<String> = game_state.opponents.iter().map(|opponent| format!("{}  ", opponent.score)).collect();
+    let opponents_len: usize = opponent_scores.iter().map(String::len).sum();
+    let duration_str = if game_state.show_frame_time {
+        format!("    {}", game_state.duration_since_last_update.as_millis())
+    } else { String::new() };
+    // the frame time makes way for the scores on a narrow terminal
+    let duration_str = if state_len + duration_str.len() + opponents_len + score_str.len() <= width {
+        duration_str
+    } else { String::new() };
+    let duration_len = duration_str.len();
+    write!(buffer, "{}", duration_str).unwrap();
+
+    buffer.right(width.saturating_sub(state_len + duration_len + opponents_len + score_str.len()));
+    for ((score, opponent), color) in opponent_scores.iter().zip(game_state.opponents.iter()).zip(battle::COLORS) {
+        let color = if opponent.snake.is_empty() { 8 } else { color as u8 };
+        buffer.set_foreground(TermColor::Color(color));
+        write!(buffer, "{score}").unwrap();
+    }
+    buffer.set_foreground(TermColor::Color(15));
+    write!(buffer, "{score_str}").unwrap();
+
+    if !game_state.light_cycle {
+        buffer.goto(game_state.number_pos.0, game_state.number_pos.1 / 2 + 1);
+        write!(buffer, "{}", game_state.current_number).unwrap();
+    }
+
+    if game_state.demo {
+        draw_box(buffer, "Press any key to play", prompt_color);
+    } else if game_state.paused {
+        draw_box(buffer, &format!("Press {action_key} to continue"), prompt_color);
+        if let Some(menu) = menu {
+            let (width, height) = buffer.size;
+            buffer.set_foreground(TermColor::Color(15));
+            buffer.goto((width / 2).saturating_sub(menu.len() / 2), height / 2 + 2);
+            write!(buffer, "{menu}").unwrap();
+            buffer.set_foreground(TermColor::Default);
+        }
+    }
+}
+
+/// Says the terminal has to grow, in as much of the middle of it as there is.
+fn draw_too_small(buffer: &mut Terminal) {
+    let (width, height) = buffer.size;
+    if width == 0 || height == 0 {
+        return;
+    }
+    let message: String = "Terminal too small".chars().take(width).collect();
+    buffer.goto((width - message.len()) / 2, height / 2);
+    write!(buffer, "{message}").unwrap();
+}
+
+/// Draws `message` in a box in the middle of the screen.
+pub(crate) fn draw_box(buffer: &mut Terminal, message: &str, prompt_color: TermColor) {
+    let (width, height) = buffer.size;
+    let box_width = message.len() + 4;
+    let box_height = 1 + 2;
+
+    let box_x = (width / 2).saturating_sub(box_width / 2);
+    let box_y = (height / 2).saturating_sub(box_height / 2);
+
+    buffer.set_foreground(TermColor::Color(15));
+    buffer.set_background(TermColor::Color(1));
+    buffer.goto(box_x, box_y);
+    for content in [
+            "\u{2580}".repeat(box_width - 2),
+            format!(" {} ", message),
+            "\u{2584}".repeat(box_width - 2)] {
+        write!(buffer, "\u{2588}{}\u{2588}", content).unwrap();
+        buffer.left(box_width);
+        buffer.down(1);
+    }
+    buffer.set_foreground(prompt_color);
+    buffer.goto(box_x + 2, box_y + 1);
+    write!(buffer, "{}", message).unwrap();
+    buffer.set_foreground(TermColor::Default);
+    buffer.set_background(TermColor::Default);
+}
+
+/// The cell showing the pixels `co` (upper half) and `ce` (lower half), or
+/// `None` when both are empty and whatever is underneath should show.
+fn pixel_cell(co: i16, ce: i16, on_edge: bool) -> Option<Cell> {
+    if co == ce && co < 0 {
+        return None;
+    }
+    let data = if co == ce { '\u{2588}' } else if ce >= 0 { '\u{2584}' } else { '\u{2580}' };
+    let (foreground, background) = if co >= 0 && ce >= 0 {
+        (TermColor::Color(ce as u8), TermColor::Color(co as u8))
+    } else if on_edge {
+        (TermColor::Color(std::cmp::max(co, ce) as u8), TermColor::Color(9))
+    } else {
+        (TermColor::Color(std::cmp::max(co, ce) as u8), TermColor::Default)
+    };
+    Some(Cell { foreground, background, data })
+}
+
+fn put_cell(buffer: &mut Terminal, x: usize, y: usize, cell: Cell) {
+    let (width, height) = buffer.size;
+    buffer.rows[y.min(height - 1)][x.min(width - 1)] = cell;
+}
+
+fn check_bottom_top(bottom: usize, y: usize) -> bool {
+    y == 0 || y == bottom - 1
+}
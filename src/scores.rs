@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+
+use crate::modes::time_attack_finished;
+
+/// How many entries a table keeps.
+const MAX_ENTRIES: usize = 10;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) score: i32,
+    pub(crate) level: u8,
+    /// The clock at every number eaten, for modes against the clock.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) splits: Vec<u64>
+}
+
+/// The best scores so far, best first, kept in a JSON file so they survive
+/// a restart.
+pub(crate) struct HighScores {
+    path: Option<PathBuf>,
+    entries: Vec<Entry>
+}
+
+impl HighScores {
+    /// Reads the table from `path`, starting out empty if there is none yet.
+    pub(crate) fn load(path: PathBuf) -> io::Result<HighScores> {
+        let entries = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err)
+        };
+        Ok(HighScores { path: Some(path), entries })
+    }
+
+    /// A table that is forgotten on exit.
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> HighScores {
+        HighScores { path: None, entries: Vec::new() }
+    }
+
+    pub(crate) fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Adds `entry` if it makes the table and saves it. Returns its place,
+    /// counting from 0. Ties go to whoever got there first.
+    pub(crate) fn record(&mut self, entry: Entry) -> io::Result<Option<usize>> {
+        let place = self.entries.iter().position(|other| other.score < entry.score).unwrap_or(self.entries.len());
+        if place >= MAX_ENTRIES {
+            return Ok(None);
+        }
+        self.entries.insert(place, entry);
+        self.entries.truncate(MAX_ENTRIES);
+
+        if let Some(path) = &self.path {
+            // written next to the table and moved over it, so a crash can't
+            // leave half a table behind
+            let temporary = path.with_extension("tmp");
+            serde_json::to_writer_pretty(BufWriter::new(File::create(&temporary)?), &self.entries)?;
+            fs::rename(&temporary, path)?;
+        }
+        Ok(Some(place))
+    }
+
+    /// The splits of `name`'s best finished run, to race against. Runs that
+    /// ran out of time don't count, however far they got.
+    pub(crate) fn best_splits(&self, name: &str) -> &[u64] {
+        self.entries.iter()
+            .find(|entry| entry.name == name && time_attack_finished(&entry.splits))
+            .map_or(&[], |entry| &entry.splits)
+    }
+
+    /// The table as lines of text, one per entry.
+    pub(crate) fn lines(&self) -> Vec<String> {
+        lines(&self.entries)
+    }
+}
+
+/// `entries` as lines of text, one per entry, best first.
+pub(crate) fn lines(entries: &[Entry]) -> Vec<String> {
+    entries.iter().enumerate()
+        .map(|(place, entry)| format!("{:>2}. {:>7}  level {}  {}", place + 1, entry.score, entry.level, entry.name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, score: i32) -> Entry {
+        Entry { name: name.to_string(), score, level: 1, splits: Vec::new() }
+    }
+
+    #[test]
+    fn keeps_the_best_in_order() {
+        let mut scores = HighScores::in_memory();
+        for score in 0..15 {
+            scores.record(entry("a", score * 100)).unwrap();
+        }
+        let kept: Vec<i32> = scores.entries().iter().map(|entry| entry.score).collect();
+        assert_eq!(kept, (5..15).rev().map(|score| score * 100).collect::<Vec<_>>());
+        assert_eq!(scores.record(entry("b", 100)).unwrap(), None);
+        assert_eq!(scores.record(entry("c", 1000)).unwrap(), Some(5));
+        assert_eq!(scores.entries()[4].name, "a", "ties go to the earlier entry");
+    }
+
+    #[test]
+    fn survives_a_reload() {
+        let path = std::env::temp_dir().join(format!("nibbles-scores-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut scores = HighScores::load(path.clone()).unwrap();
+        scores.record(entry("a", 300)).unwrap();
+        scores.record(entry("b", 500)).unwrap();
+
+        let names: Vec<String> = HighScores::load(path.clone()).unwrap().entries().iter().map(|entry| entry.name.clone()).collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(names, ["b", "a"]);
+    }
+
+    #[test]
+    fn races_against_the_players_own_best_finished_run() {
+        let mut scores = HighScores::in_memory();
+        let finished = |name: &str, score, split| Entry { splits: vec![split; crate::LEVELS.len() * 10], ..entry(name, score) };
+        // runs out of time, so it scores nothing, but still makes the table
+        scores.record(Entry { splits: vec![1; 30], ..entry("a", 0) }).unwrap();
+        assert!(scores.best_splits("a").is_empty());
+
+        scores.record(finished("a", 500, 2)).unwrap();
+        scores.record(finished("b", 900, 3)).unwrap();
+        scores.record(finished("a", 700, 4)).unwrap();
+        assert_eq!(scores.best_splits("a")[0], 4);
+        assert_eq!(scores.best_splits("b")[0], 3);
+        assert!(scores.best_splits("c").is_empty());
+    }
+}
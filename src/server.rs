@@ -0,0 +1,336 @@
+//! `--serve`: plays over TCP with telnet clients. Every connection gets a
+//! game of its own, sized to the client's window and drawn by its own
+//! renderer and backend, so sessions only share the high-score table.
+//!
+//! Plain netcat works too, with the local terminal in raw mode
+//! (`stty raw -echo; nc host port`), but shows the telnet negotiation as a
+//! few stray characters and stays at the default size.
+
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, queue, style};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Interest, Registry, Token};
+use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGTERM};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::backend::{Backend, RecordingBackend};
+use crate::data::data_dir;
+use crate::events::{EventLoop, Peer, Slots, accept_all, receive, send};
+use crate::modes::Mode;
+use crate::render::Renderer;
+use crate::scores::{Entry, HighScores};
+use crate::telnet::{self, Decoder};
+use crate::{Board, GameEvent, GameState, UPDATE_TIME, apply_event, create_game_state, game_over, shown_board};
+
+/// The window size of clients that don't tell us theirs.
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+/// Window sizes are kept within these, so no client can make the renderer
+/// work with a screen it can't lay out, or one that takes all our memory.
+const MIN_SIZE: (u16, u16) = (40, 16);
+const MAX_SIZE: (u16, u16) = (500, 200);
+/// How much output may wait for a slow client before its frames are skipped.
+const MAX_BACKLOG: usize = 64 * 1024;
+/// Connections beyond this many are turned away.
+const MAX_SESSIONS: usize = 256;
+
+/// Serves games on `port` on every interface until interrupted. Port 0 picks
+/// a free one; either way the address goes to stdout first thing.
+pub(crate) fn run(port: u16, mode: Mode) -> io::Result<()> {
+    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port)))?;
+    let mut stdout = io::stdout();
+    writeln!(stdout, "listening on {}", listener.local_addr()?)?;
+    stdout.flush()?;
+
+    let terminate = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM, SIGHUP] {
+        signal_hook::flag::register(signal, Arc::clone(&terminate))?;
+    }
+
+    let scores = HighScores::load(data_dir()?.join(mode.high_scores_file()))?;
+    let mut event_loop = EventLoop::new(UPDATE_TIME)?;
+    event_loop.attach(Box::new(Server { base: 0, listener, sessions: Slots::new(), scores, mode }))?;
+    while !terminate.load(Ordering::SeqCst) {
+        event_loop.wait(&mut Vec::new(), None)?;
+    }
+    // dropping the loop says goodbye to whoever is still playing
+    Ok(())
+}
+
+struct Server {
+    base: usize,
+    listener: TcpListener,
+    sessions: Slots<Session>,
+    scores: HighScores,
+    /// What every session plays.
+    mode: Mode
+}
+
+impl Server {
+    fn accept(&mut self, registry: &Registry) {
+        let accepted = accept_all(|| self.listener.accept(), |(mut stream, address)| {
+            let index = self.sessions.vacant();
+            if index >= MAX_SESSIONS {
+                let _ = stream.write(b"The server is full, try again later.\r\n");
+                return;
+            }
+            let token = Token(self.base + 1 + index);
+            if let Err(err) = registry.register(&mut stream, token, Interest::READABLE | Interest::WRITABLE) {
+                eprintln!("{address}: {err}");
+                return;
+            }
+            log(format_args!("{address} joined"));
+            self.sessions.insert(index, Session::new(stream, address, &self.scores, self.mode));
+        });
+        if let Err(err) = accepted {
+            eprintln!("accepting a connection failed: {err}");
+        }
+    }
+
+    /// Ends session `index` unless `result` says it goes on. Nothing that
+    /// happens to one session is an error for the server.
+    fn settle(&mut self, registry: &Registry, index: usize, result: io::Result<bool>) {
+        let reason = match result {
+            Ok(true) => return,
+            Ok(false) => String::from("left"),
+            Err(err) => format!("dropped: {err}")
+        };
+        if let Some(mut session) = self.sessions.remove(index) {
+            let _ = registry.deregister(&mut session.stream);
+            log(format_args!("{} {reason}", session.address));
+        }
+    }
+}
+
+impl Peer for Server {
+    fn register(&mut self, registry: &Registry, base: usize) -> io::Result<()> {
+        self.base = base;
+        registry.register(&mut self.listener, Token(base), Interest::READABLE)
+    }
+
+    fn ready(&mut self, registry: &Registry, offset: usize, _: &mut Vec<GameEvent>) -> io::Result<()> {
+        if offset == 0 {
+            self.accept(registry);
+            return Ok(());
+        }
+        let index = offset - 1;
+        if let Some(session) = self.sessions.get_mut(index) {
+            let result = session.ready(&mut self.scores);
+            self.settle(registry, index, result);
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, registry: &Registry, ticks: u32) -> io::Result<()> {
+        for index in 0..self.sessions.len() {
+            if let Some(session) = self.sessions.get_mut(index) {
+                let result = session.tick(ticks, &mut self.scores);
+                self.settle(registry, index, result);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Notes comings and goings on stdout, unless nobody reads it anymore.
+fn log(line: std::fmt::Arguments) {
+    let _ = writeln!(io::stdout(), "{line}");
+}
+
+/// One client's game. Its methods return whether the session goes on.
+struct Session {
+    stream: TcpStream,
+    address: SocketAddr,
+    decoder: Decoder,
+    game_state: GameState,
+    renderer: Renderer,
+    backend: RecordingBackend,
+    /// Output the client hasn't taken yet.
+    backlog: Vec<u8>,
+    /// Whether the lost game went into the high scores already.
+    recorded: bool
+}
+
+impl Session {
+    fn new(stream: TcpStream, address: SocketAddr, scores: &HighScores, mode: Mode) -> Session {
+        let mut game_state = create_game_state(rand::random(), DEFAULT_SIZE);
+        game_state.mode = mode;
+        // it changes every tick, which is a waste on the network
+        game_state.show_frame_time = false;
+        let mut renderer = Renderer::new().animated(false);
+        renderer.set_high_scores(scores.lines());
+        renderer.set_best_splits(scores.best_splits(&address.ip().to_string()).to_vec());
+
+        let mut backlog = telnet::NEGOTIATION.to_vec();
+        queue!(backlog, EnterAlternateScreen, cursor::Hide).unwrap();
+        let mut session = Session {
+            stream,
+            address,
+            decoder: Decoder::default(),
+            game_state,
+            renderer,
+            backend: RecordingBackend::recording(),
+            backlog,
+            recorded: false
+        };
+        let board = shown_board(&mut session.game_state);
+        session.draw(&board);
+        session
+    }
+
+    fn ready(&mut self, scores: &mut HighScores) -> io::Result<bool> {
+        let mut events = Vec::new();
+        let mut input = Vec::new();
+        if !receive(&mut self.stream, &mut input)? {
+            return Ok(false);
+        }
+        self.decoder.feed(&input, &mut events);
+
+        let mut shown = None;
+        for event in events {
+            let event = match event {
+                GameEvent::Quit => return Ok(false),
+                GameEvent::Resize(width, height) => GameEvent::Resize(
+                    width.clamp(MIN_SIZE.0, MAX_SIZE.0),
+                    height.clamp(MIN_SIZE.1, MAX_SIZE.1)
+                ),
+                event => event
+            };
+            shown = apply_event(event, &mut self.game_state, &mut self.backend).or(shown);
+        }
+        self.finish(shown, scores)
+    }
+
+    fn tick(&mut self, ticks: u32, scores: &mut HighScores) -> io::Result<bool> {
+        let mut shown = None;
+        for _ in 0..ticks {
+            shown = apply_event(GameEvent::Timeout, &mut self.game_state, &mut self.backend).or(shown);
+        }
+        self.finish(shown, scores)
+    }
+
+    /// Records a lost game, draws `shown` if there's a new frame and sends
+    /// what the client can take.
+    fn finish(&mut self, shown: Option<Rc<Board>>, scores: &mut HighScores) -> io::Result<bool> {
+        if !game_over(&self.game_state) {
+            self.recorded = false;
+        } else if !self.recorded {
+            self.recorded = true;
+            let entry = Entry {
+                name: self.address.ip().to_string(),
+                score: self.game_state.score,
+                level: self.game_state.level,
+                splits: self.game_state.splits.clone()
+            };
+            if let Err(err) = scores.record(entry) {
+                eprintln!("saving the high scores failed: {err}");
+            }
+            self.renderer.set_high_scores(scores.lines());
+            self.renderer.set_best_splits(scores.best_splits(&self.address.ip().to_string()).to_vec());
+        }
+
+        if let Some(board) = shown {
+            self.draw(&board);
+        }
+        self.flush()
+    }
+
+    /// Adds the changes since the last frame to the backlog. A client that
+    /// falls behind misses frames, and gets everything that changed meanwhile
+    /// with the next one it has room for.
+    fn draw(&mut self, board: &Rc<Board>) {
+        if self.backlog.len() > MAX_BACKLOG {
+            return;
+        }
+        let frame = self.renderer.render(&self.game_state, board);
+        // writing to memory can't fail
+        self.backend.draw(frame).unwrap();
+        self.backlog.extend_from_slice(&self.backend.take_bytes());
+    }
+
+    fn flush(&mut self) -> io::Result<bool> {
+        send(&mut self.stream, &mut self.backlog)?;
+        Ok(true)
+    }
+}
+
+impl Drop for Session {
+    /// Gives the client its screen back, as far as it still listens.
+    fn drop(&mut self) {
+        let _ = queue!(self.backlog, style::ResetColor, LeaveAlternateScreen, cursor::Show);
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mio::Poll;
+
+    use super::*;
+
+    fn server() -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        Server { base: 0, listener, sessions: Slots::new(), scores: HighScores::in_memory(), mode: Mode::Campaign }
+    }
+
+    fn lose(server: &mut Server, index: usize, score: i32) {
+        let game_state = &mut server.sessions.get_mut(index).unwrap().game_state;
+        game_state.score = score;
+        game_state.lives = 0;
+    }
+
+    #[test]
+    fn lost_games_share_one_table() {
+        let poll = Poll::new().unwrap();
+        let mut server = server();
+        let address = server.listener.local_addr().unwrap();
+        let _clients: Vec<_> = (0..2).map(|_| std::net::TcpStream::connect(address).unwrap()).collect();
+        server.accept(poll.registry());
+        assert_eq!(server.sessions.len(), 2);
+
+        lose(&mut server, 0, 1200);
+        server.tick(poll.registry(), 1).unwrap();
+        lose(&mut server, 1, 500);
+        // a lost game only counts once, however long its screen stays up
+        server.tick(poll.registry(), 3).unwrap();
+
+        let scores: Vec<i32> = server.scores.entries().iter().map(|entry| entry.score).collect();
+        assert_eq!(scores, [1200, 500]);
+        assert_eq!(server.scores.entries()[0].name, "127.0.0.1");
+
+        // the second game's screen lists the first game's score too
+        let session = server.sessions.get_mut(1).unwrap();
+        let board = shown_board(&mut session.game_state);
+        let frame = session.renderer.render(&session.game_state, &board);
+        let text: String = frame.rows.iter().flatten().map(|cell| cell.data).collect();
+        assert!(text.contains("High scores") && text.contains("   1200  level 1  127.0.0.1"), "{text}");
+    }
+
+    #[test]
+    fn closed_connections_free_their_slot() {
+        let poll = Poll::new().unwrap();
+        let mut server = server();
+        let address = server.listener.local_addr().unwrap();
+        let client = std::net::TcpStream::connect(address).unwrap();
+        server.accept(poll.registry());
+        drop(client);
+
+        // the end of the stream may take a moment to arrive
+        for _ in 0..100 {
+            server.ready(poll.registry(), 1, &mut Vec::new()).unwrap();
+            if server.sessions.get_mut(0).is_none() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(server.sessions.get_mut(0).is_none());
+
+        let _client = std::net::TcpStream::connect(address).unwrap();
+        server.accept(poll.registry());
+        assert_eq!(server.sessions.len(), 1);
+        assert!(server.sessions.get_mut(0).is_some());
+    }
+}
@@ -0,0 +1,264 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use crate::{Board, SnakeCell};
+
+/// The snake's cells from head to tail, kept in a ring buffer so moving only
+/// touches both ends, plus a count of cells per board pixel so collisions
+/// don't have to walk the body.
+#[derive(Default, Serialize)]
+pub(crate) struct Snake {
+    cells: VecDeque<SnakeCell>,
+    /// How many cells sit on each pixel, row by row. A cell can be counted
+    /// more than once while the snake grows, since new cells start out as
+    /// copies of the tail.
+    #[serde(skip)]
+    occupancy: Vec<u16>,
+    /// The board dimensions `occupancy` is laid out for.
+    #[serde(skip)]
+    dimensions: (usize, usize)
+}
+
+impl Snake {
+    /// Replaces the snake with `cells`, head first.
+    pub(crate) fn reset(&mut self, cells: Vec<SnakeCell>) {
+        self.clear();
+        for cell in cells {
+            self.count(cell, 1);
+            self.cells.push_back(cell);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.cells.clear();
+        self.occupancy.fill(0);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub(crate) fn head(&self) -> SnakeCell {
+        self.cells[0]
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &SnakeCell> {
+        self.cells.iter()
+    }
+
+    /// Whether any of the snake's cells is on `cell`, which has to be on
+    /// `board`.
+    pub(crate) fn contains(&mut self, cell: SnakeCell, board: &Board) -> bool {
+        self.fit(board);
+        self.occupancy[self.index(cell).unwrap()] > 0
+    }
+
+    /// Adds a copy of the tail, which stays behind when the snake moves next.
+    pub(crate) fn grow(&mut self) {
+        let tail = *self.cells.back().unwrap();
+        self.count(tail, 1);
+        self.cells.push_back(tail);
+    }
+
+    /// Moves the head to `head`, which has to be on `board`, and lets the tail
+    /// follow. Returns `false` without moving if the snake would bite itself.
+    /// The tail is about to leave its pixel, so moving onto it is fine unless
+    /// the snake is growing there.
+    pub(crate) fn advance(&mut self, head: SnakeCell, board: &Board) -> bool {
+        self.fit(board);
+
+        let tail = *self.cells.back().unwrap();
+        let leaving = (tail == head) as u16;
+        if self.occupancy[self.index(head).unwrap()] > leaving {
+            return false;
+        }
+
+        self.count(tail, -1);
+        self.cells.pop_back();
+        self.count(head, 1);
+        self.cells.push_front(head);
+        true
+    }
+
+    /// Lays `occupancy` out for `board`, recounting the cells if the board's
+    /// dimensions changed since the last move.
+    fn fit(&mut self, board: &Board) {
+        let dimensions = (board.width, board.rows.len());
+        if dimensions == self.dimensions {
+            return;
+        }
+        self.dimensions = dimensions;
+        self.occupancy = vec![0; dimensions.0 * dimensions.1];
+        for index in 0..self.cells.len() {
+            self.count(self.cells[index], 1);
+        }
+    }
+
+    fn index(&self, SnakeCell(x, y): SnakeCell) -> Option<usize> {
+        let (width, height) = self.dimensions;
+        let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+        (x < width && y < height).then_some(y * width + x)
+    }
+
+    /// Cells off the board aren't counted; they only exist until
+    /// `fit_to_board` drops the snake after a resize.
+    fn count(&mut self, cell: SnakeCell, delta: i16) {
+        if let Some(index) = self.index(cell) {
+            self.occupancy[index] = self.occupancy[index].wrapping_add_signed(delta);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{Direction, GameEvent, GameRng, GameState, advance, create_game_state, level_board, update};
+    use super::*;
+
+    /// The snake as `update` kept it before it became a `Snake`: a vector
+    /// shifted by one on every move, checked against the new head cell by cell.
+    struct Reference {
+        snake: Vec<SnakeCell>,
+        direction: Direction,
+        number_pos: (usize, usize),
+        current_number: u8,
+        extending: u8,
+        paused: bool,
+        score: i32,
+        lives: u8,
+        level: u8
+    }
+
+    impl Reference {
+        fn of(game_state: &GameState) -> Reference {
+            Reference {
+                snake: game_state.snake.iter().copied().collect(),
+                direction: game_state.snake_direction,
+                number_pos: game_state.number_pos,
+                current_number: game_state.current_number,
+                extending: game_state.extending,
+                paused: game_state.paused,
+                score: game_state.score,
+                lives: game_state.lives,
+                level: game_state.level
+            }
+        }
+
+        fn mistake(&mut self) {
+            self.lives -= 1;
+            if self.lives > 0 {
+                self.score -= 1000;
+                self.current_number = 1;
+                self.snake = Vec::new();
+                self.number_pos = (0, 0);
+                self.direction = Direction::Right;
+                self.extending = 0;
+            }
+            self.paused = true;
+        }
+
+        fn update(&mut self, board: &Board) {
+            if self.extending != 0 {
+                self.snake.push(*self.snake.last().unwrap());
+                self.extending -= 1;
+            }
+
+            let mut head = self.snake[0];
+            match self.direction {
+                Direction::Left | Direction::Right => head.0 += self.direction.as_integer(),
+                Direction::Up | Direction::Down => head.1 += self.direction.as_integer()
+            }
+
+            if head.0 < 1 || head.1 < 1 || head.0 >= board.width as i32 || head.1 > board.height as i32 {
+                return self.mistake();
+            }
+            if board.lookup(head.0 as usize, head.1 as usize) >= 0 {
+                return self.mistake();
+            }
+            for idx in (1..self.snake.len()).rev() {
+                let cell = self.snake[idx - 1];
+                self.snake[idx] = cell;
+                if cell == head {
+                    return self.mistake();
+                }
+            }
+
+            self.snake[0] = head;
+            let number = self.number_pos;
+            if (head.0 as usize, (head.1 - head.1 % 2) as usize) == (number.0, number.1 - number.1 % 2) {
+                if self.current_number == 10 {
+                    self.level += 1;
+                    self.snake = Vec::new();
+                    self.direction = Direction::Right;
+                    self.current_number = 1;
+                    self.number_pos = (0, 0);
+                    self.score += self.current_number as i32 * 100;
+                    self.extending = 0;
+                    self.paused = true;
+                    return;
+                }
+                self.score += self.current_number as i32 * 100;
+                self.extending = self.current_number * 4;
+                self.current_number += 1;
+                self.number_pos = (0, 0);
+            }
+        }
+
+        /// Everything `update` can change, except a snake that has been
+        /// given up on because the game is over.
+        fn observable(&self) -> impl PartialEq + std::fmt::Debug {
+            let snake = if self.lives == 0 { Vec::new() } else { self.snake.clone() };
+            (snake, self.direction.as_integer(), self.number_pos, self.current_number, self.extending,
+             self.paused, self.score, self.lives, self.level)
+        }
+    }
+
+    #[test]
+    fn update_matches_the_vector_snake() {
+        for seed in 0..20 {
+            let mut game_state = create_game_state(seed, (50, 20));
+            let mut rng = GameRng::seed_from_u64(seed);
+            for tick in 0..5000 {
+                // respawns the snake and the number after a mistake
+                advance(GameEvent::Redraw, &mut game_state);
+                if game_state.lives == 0 {
+                    game_state = create_game_state(rng.gen(), (50, 20));
+                    advance(GameEvent::Redraw, &mut game_state);
+                }
+                if rng.gen_ratio(1, 3) {
+                    let turn = [GameEvent::Up, GameEvent::Right, GameEvent::Down, GameEvent::Left][rng.gen_range(0..4)];
+                    advance(turn, &mut game_state);
+                }
+                // grow now and then, so the snake gets long enough to bite itself
+                if rng.gen_ratio(1, 4) {
+                    game_state.extending = game_state.extending.max(rng.gen_range(0..40));
+                }
+
+                let board = level_board(&mut game_state);
+                let mut reference = Reference::of(&game_state);
+                reference.update(&board);
+                update(&mut game_state, &board);
+                assert_eq!(Reference::of(&game_state).observable(), reference.observable(), "seed {seed}, tick {tick}");
+                game_state.paused = false;
+            }
+        }
+    }
+
+    #[test]
+    fn moving_onto_the_tail_is_allowed_unless_growing() {
+        let board = Board::new(10, 10);
+        let cells = vec![SnakeCell(2, 2), SnakeCell(3, 2), SnakeCell(3, 3), SnakeCell(2, 3)];
+
+        let mut snake = Snake::default();
+        snake.reset(cells.clone());
+        assert!(snake.advance(SnakeCell(2, 3), &board));
+        assert_eq!(snake.head(), SnakeCell(2, 3));
+        assert_eq!(snake.iter().count(), 4);
+
+        snake.reset(cells);
+        snake.grow();
+        assert!(!snake.advance(SnakeCell(2, 3), &board));
+        assert_eq!(snake.head(), SnakeCell(2, 2));
+    }
+}
@@ -0,0 +1,176 @@
+//! Golden-frame tests for the `Renderer`.
+//!
+//! Every test renders a `GameState` into a `Terminal` of a fixed size and
+//! compares its text serialization against a file in `snapshots/`. Run the
+//! tests with `UPDATE_SNAPSHOTS=1` to rewrite the files instead.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::battle::{self, Difficulty};
+use crate::terminal::{Terminal, TermColor};
+use crate::{GameState, LEVELS, check_unitialized_state, create_game_state, level_board};
+use crate::render::Renderer;
+
+const SIZES: &[(u16, u16)] = &[(40, 16), (80, 24), (132, 43)];
+const SEED: u64 = 0x6e_6962_626c_6573;
+
+/// Keys for the colour map, handed out in order of first appearance.
+/// The default colour pair always gets `.`.
+const COLOR_KEYS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// Serializes `terminal` into its characters, followed by a map of the same
+/// shape assigning each cell a key and a legend of what the keys mean.
+pub(crate) fn serialize(terminal: &Terminal) -> String {
+    let mut legend: Vec<(TermColor, TermColor)> = vec![(TermColor::Default, TermColor::Default)];
+    let mut text = String::new();
+    let mut colors = String::new();
+
+    for row in terminal.rows.iter() {
+        for cell in row.iter() {
+            text.push(cell.data);
+            let pair = (cell.foreground, cell.background);
+            let index = match legend.iter().position(|&p| p == pair) {
+                Some(index) => index,
+                None => {
+                    legend.push(pair);
+                    legend.len() - 1
+                }
+            };
+            colors.push(if index == 0 { '.' } else { key(index) });
+        }
+        text.push('\n');
+        colors.push('\n');
+    }
+
+    let (width, height) = terminal.size;
+    let mut result = format!("size {width}x{height}\n--- text\n{text}--- colors\n{colors}--- legend\n");
+    for (index, (foreground, background)) in legend.iter().enumerate().skip(1) {
+        writeln!(result, "{} fg={} bg={}", key(index), color_name(*foreground), color_name(*background)).unwrap();
+    }
+    result
+}
+
+fn key(index: usize) -> char {
+    COLOR_KEYS.chars().nth(index - 1).expect("too many distinct colours for a snapshot")
+}
+
+fn color_name(color: TermColor) -> String {
+    match color {
+        TermColor::Default => "default".to_string(),
+        TermColor::Color(c) => c.to_string()
+    }
+}
+
+/// Renders `game_state` as it would appear on a terminal of `size`, building
+/// the level's board for that size first.
+pub(crate) fn render_at(game_state: &mut GameState, size: (u16, u16)) -> Terminal {
+    game_state.terminal_size = size;
+    game_state.cached_board = None;
+    let board = level_board(game_state);
+    check_unitialized_state(game_state, &board);
+    Renderer::new().render(game_state, &board).clone()
+}
+
+fn assert_snapshot(name: &str, terminal: &Terminal) {
+    let actual = serialize(terminal);
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("snapshots").join(format!("{name}.snap"));
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing snapshot {}, run with UPDATE_SNAPSHOTS=1 to create it", path.display()));
+    if expected != actual {
+        let line = zip_lines(&expected, &actual);
+        panic!(
+            "snapshot {name} differs at line {}:\n  expected: {:?}\n  actual:   {:?}\nrun with UPDATE_SNAPSHOTS=1 to accept the change",
+            line.0 + 1, line.1, line.2
+        );
+    }
+}
+
+fn zip_lines<'a>(expected: &'a str, actual: &'a str) -> (usize, &'a str, &'a str) {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for index in 0.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => continue,
+            (e, a) => return (index, e.unwrap_or("<end>"), a.unwrap_or("<end>"))
+        }
+    }
+    unreachable!()
+}
+
+fn playing_state(level: u8) -> GameState {
+    let mut game_state = create_game_state(SEED, SIZES[0]);
+    game_state.level = level;
+    game_state.paused = false;
+    game_state
+}
+
+#[test]
+fn level_layouts() {
+    for level in 1..=LEVELS.len() as u8 {
+        for &(width, height) in SIZES {
+            let mut game_state = playing_state(level);
+            let frame = render_at(&mut game_state, (width, height));
+            assert_snapshot(&format!("level{level}_{width}x{height}"), &frame);
+        }
+    }
+}
+
+#[test]
+fn pause_overlay() {
+    for &(width, height) in SIZES {
+        let mut game_state = create_game_state(SEED, (width, height));
+        let frame = render_at(&mut game_state, (width, height));
+        assert_snapshot(&format!("paused_{width}x{height}"), &frame);
+    }
+}
+
+#[test]
+fn game_over_screen() {
+    for &(width, height) in SIZES {
+        let mut game_state = playing_state(3);
+        game_state.lives = 0;
+        game_state.score = -2700;
+        let frame = render_at(&mut game_state, (width, height));
+        assert_snapshot(&format!("game_over_{width}x{height}"), &frame);
+    }
+}
+
+#[test]
+fn hud() {
+    for &(width, height) in SIZES {
+        let mut game_state = playing_state(2);
+        game_state.lives = 3;
+        game_state.score = 12_300;
+        game_state.current_number = 7;
+        let frame = render_at(&mut game_state, (width, height));
+        assert_snapshot(&format!("hud_{width}x{height}"), &frame);
+    }
+}
+
+#[test]
+fn battle() {
+    for &(width, height) in SIZES {
+        let mut game_state = playing_state(1);
+        battle::add_opponents(&mut game_state, 3, Difficulty::Normal);
+        game_state.score = 1_500;
+        let frame = render_at(&mut game_state, (width, height));
+        assert_snapshot(&format!("battle_{width}x{height}"), &frame);
+    }
+}
+
+#[test]
+fn serialize_marks_colours() {
+    let mut terminal = Terminal::new(3, 1);
+    terminal.set_foreground(TermColor::Color(9));
+    write!(terminal, "ab").unwrap();
+    assert_eq!(serialize(&terminal), "size 3x1\n--- text\nab \n--- colors\naa.\n--- legend\na fg=9 bg=default\n");
+}
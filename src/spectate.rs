@@ -0,0 +1,339 @@
+//! Lets others watch a game while it's played: every frame goes out to any
+//! number of viewers on a TCP port or a Unix socket, as the same escape
+//! sequences the player's terminal gets. Viewers only watch, whatever they
+//! send is ignored.
+//!
+//! ```text
+//! nibbles --spectate-port 4000                # nc localhost 4000
+//! nibbles --spectate-socket /tmp/nibbles.sock # socat - UNIX-CONNECT:/tmp/nibbles.sock
+//! ```
+//!
+//! A port only takes viewers on this machine, unless `--spectate-public`
+//! opens it to every interface.
+//!
+//! A viewer's terminal wants to be at least as large as the player's.
+
+use crossterm::{cursor, queue, style};
+use mio::event::Source;
+use mio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use mio::{Interest, Registry, Token};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::GameEvent;
+use crate::backend::{Backend, FrameStats, RecordingBackend};
+use crate::events::{Peer, Slots, accept_all, receive, send};
+use crate::terminal::Terminal;
+
+/// How much output may wait for a slow viewer. Past that it misses frames,
+/// and gets a whole one once it caught up.
+const MAX_BACKLOG: usize = 64 * 1024;
+
+pub(crate) enum Address {
+    Tcp(SocketAddr),
+    Socket(PathBuf)
+}
+
+/// The viewers of a game and what they were sent.
+pub(crate) struct Spectators {
+    listener: Listener,
+    viewers: Slots<Viewer>,
+    /// Turns each frame into its difference to the one before, which is what
+    /// every viewer that keeps up gets.
+    backend: RecordingBackend,
+    /// The latest frame, for viewers that need all of it.
+    frame: Option<Terminal>,
+    synchronized: bool
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    /// Removes its socket file when dropped.
+    Unix(UnixListener, PathBuf)
+}
+
+struct Viewer {
+    stream: Stream,
+    /// Output the viewer hasn't taken yet.
+    backlog: Vec<u8>,
+    /// Whether the viewer missed frames, or never got one, and needs all of
+    /// the next.
+    behind: bool
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream)
+}
+
+impl Spectators {
+    /// Starts listening for viewers on `address`. Frames are wrapped in synchronized updates if `synchronized`.
+    pub(crate) fn bind(address: &Address, synchronized: bool) -> io::Result<Spectators> {
+        let listener = match address {
+            Address::Tcp(address) => Listener::Tcp(TcpListener::bind(*address)?),
+            Address::Socket(path) => Listener::Unix(UnixListener::bind(path)?, path.clone())
+        };
+        Ok(Spectators {
+            listener,
+            viewers: Slots::new(),
+            backend: RecordingBackend::recording().synchronized(synchronized),
+            frame: None,
+            synchronized
+        })
+    }
+
+    /// Where viewers connect, for telling people.
+    pub(crate) fn address(&self) -> io::Result<String> {
+        Ok(match &self.listener {
+            Listener::Tcp(listener) => listener.local_addr()?.to_string(),
+            Listener::Unix(_, path) => path.display().to_string()
+        })
+    }
+
+    fn accept(&mut self, registry: &Registry, base: usize) {
+        let listener = &self.listener;
+        let accepted = || match listener {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Listener::Unix(listener, _) => listener.accept().map(|(stream, _)| Stream::Unix(stream))
+        };
+        // the game goes on whether viewers can join or not
+        let _ = accept_all(accepted, |mut stream| {
+            let index = self.viewers.vacant();
+            let token = Token(base + 1 + index);
+            if registry.register(stream.source(), token, Interest::READABLE | Interest::WRITABLE).is_err() {
+                return;
+            }
+            let mut viewer = Viewer { stream, backlog: Vec::new(), behind: true };
+            queue!(viewer.backlog, cursor::Hide).unwrap();
+            if let Some(frame) = &self.frame {
+                viewer.catch_up(frame, self.synchronized);
+            }
+            self.viewers.insert(index, viewer);
+        });
+    }
+
+    /// Takes what viewer `index` sent, to notice it leaving, and sends what it
+    /// can take.
+    fn ready(&mut self, registry: &Registry, index: usize) {
+        let Some(viewer) = self.viewers.get_mut(index) else {
+            return;
+        };
+        let open = receive(&mut viewer.stream, &mut Vec::new()).unwrap_or(false);
+        if let (true, Some(frame)) = (viewer.behind && viewer.backlog.is_empty(), &self.frame) {
+            viewer.catch_up(frame, self.synchronized);
+        }
+        if !open || viewer.flush().is_err() {
+            let _ = registry.deregister(viewer.stream.source());
+            self.viewers.remove(index);
+        }
+    }
+
+    /// Sends `frame` to every viewer.
+    fn show(&mut self, frame: &Terminal) {
+        // writing to memory can't fail
+        self.backend.draw(frame).unwrap();
+        let diff = self.backend.take_bytes();
+        match &mut self.frame {
+            Some(last) => last.clone_from(frame),
+            None => self.frame = Some(frame.clone())
+        }
+
+        let synchronized = self.synchronized;
+        // the stream closes along with a viewer that's dropped, which takes it
+        // out of the poll as well
+        self.viewers.retain(|viewer| {
+            if viewer.backlog.len() > MAX_BACKLOG {
+                viewer.behind = true;
+            }
+            if !viewer.behind {
+                viewer.backlog.extend_from_slice(&diff);
+            } else if viewer.backlog.is_empty() {
+                viewer.catch_up(frame, synchronized);
+            }
+            viewer.flush().is_ok()
+        });
+    }
+
+    /// Repaints everything with the next frame, for when the player's screen
+    /// is repainted.
+    fn invalidate(&mut self) {
+        self.backend.invalidate().unwrap();
+    }
+}
+
+impl Drop for Spectators {
+    /// Gives viewers their cursor back, as far as they still listen.
+    fn drop(&mut self) {
+        for viewer in self.viewers.iter_mut() {
+            let _ = queue!(viewer.backlog, style::ResetColor, cursor::Show);
+            let _ = viewer.flush();
+        }
+        if let Listener::Unix(_, path) = &self.listener {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Viewer {
+    /// Queues all of `frame`, starting from a cleared screen.
+    fn catch_up(&mut self, frame: &Terminal, synchronized: bool) {
+        let mut backend = RecordingBackend::recording().synchronized(synchronized);
+        backend.draw(frame).unwrap();
+        self.backlog.extend_from_slice(&backend.take_bytes());
+        self.behind = false;
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        send(&mut self.stream, &mut self.backlog)
+    }
+}
+
+impl Stream {
+    fn source(&mut self) -> &mut dyn Source {
+        match self {
+            Stream::Tcp(stream) => stream,
+            Stream::Unix(stream) => stream
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buffer),
+            Stream::Unix(stream) => stream.read(buffer)
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(bytes),
+            Stream::Unix(stream) => stream.write(bytes)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Shows frames on the player's backend and to the spectators.
+pub(crate) struct Broadcast<'a> {
+    backend: &'a mut dyn Backend,
+    spectators: Rc<RefCell<Spectators>>
+}
+
+impl<'a> Broadcast<'a> {
+    pub(crate) fn new(backend: &'a mut dyn Backend, spectators: Rc<RefCell<Spectators>>) -> Broadcast<'a> {
+        Broadcast { backend, spectators }
+    }
+}
+
+impl Backend for Broadcast<'_> {
+    fn draw(&mut self, frame: &Terminal) -> io::Result<FrameStats> {
+        let stats = self.backend.draw(frame)?;
+        self.spectators.borrow_mut().show(frame);
+        Ok(stats)
+    }
+
+    fn invalidate(&mut self) -> io::Result<()> {
+        self.spectators.borrow_mut().invalidate();
+        self.backend.invalidate()
+    }
+}
+
+/// Lets viewers in and keeps sending to those that fell behind, between
+/// frames.
+pub(crate) struct SpectatorSocket {
+    spectators: Rc<RefCell<Spectators>>,
+    base: usize
+}
+
+impl SpectatorSocket {
+    pub(crate) fn new(spectators: Rc<RefCell<Spectators>>) -> SpectatorSocket {
+        SpectatorSocket { spectators, base: 0 }
+    }
+}
+
+impl Peer for SpectatorSocket {
+    fn register(&mut self, registry: &Registry, base: usize) -> io::Result<()> {
+        self.base = base;
+        let mut spectators = self.spectators.borrow_mut();
+        let source: &mut dyn Source = match &mut spectators.listener {
+            Listener::Tcp(listener) => listener,
+            Listener::Unix(listener, _) => listener
+        };
+        registry.register(source, Token(base), Interest::READABLE)
+    }
+
+    fn ready(&mut self, registry: &Registry, offset: usize, _: &mut Vec<GameEvent>) -> io::Result<()> {
+        let mut spectators = self.spectators.borrow_mut();
+        match offset {
+            0 => spectators.accept(registry, self.base),
+            offset => spectators.ready(registry, offset - 1)
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write as _;
+
+    use super::*;
+
+    fn frame(text: &str) -> Terminal {
+        let mut frame = Terminal::new(16, 2);
+        write!(frame, "{text}").unwrap();
+        frame
+    }
+
+    fn viewer(stream: std::os::unix::net::UnixStream) -> Viewer {
+        stream.set_nonblocking(true).unwrap();
+        Viewer { stream: Stream::Unix(UnixStream::from_std(stream)), backlog: Vec::new(), behind: false }
+    }
+
+    fn received(stream: &mut std::os::unix::net::UnixStream) -> String {
+        stream.set_nonblocking(true).unwrap();
+        let mut bytes = Vec::new();
+        let _ = stream.read_to_end(&mut bytes);
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn late_and_slow_viewers_get_whole_frames() {
+        let path = std::env::temp_dir().join(format!("nibbles-spectate-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut spectators = Spectators::bind(&Address::Socket(path.clone()), false).unwrap();
+        spectators.show(&frame("first frame"));
+
+        let (early, mut early_end) = std::os::unix::net::UnixStream::pair().unwrap();
+        spectators.viewers.insert(0, viewer(early));
+        let (late, mut late_end) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut late = viewer(late);
+        late.catch_up(spectators.frame.as_ref().unwrap(), false);
+        spectators.viewers.insert(1, late);
+        // one that missed frames and has taken everything it was sent since
+        let (slow, mut slow_end) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut slow = viewer(slow);
+        slow.behind = true;
+        spectators.viewers.insert(2, slow);
+
+        spectators.show(&frame("first framE"));
+        let early = received(&mut early_end);
+        assert!(early.ends_with('E') && !early.contains("first"), "viewers that keep up only get what changed: {early:?}");
+        let late = received(&mut late_end);
+        assert!(late.contains("\x1b[2J") && late.contains("frame") && late.ends_with('E'), "{late:?}");
+        let slow = received(&mut slow_end);
+        assert!(slow.contains("\x1b[2J") && slow.contains("framE"), "{slow:?}");
+
+        drop(spectators);
+        assert!(!path.exists(), "the socket should be removed");
+    }
+}
@@ -0,0 +1,185 @@
+//! Just enough telnet to play over a plain TCP connection: the negotiation
+//! that puts clients into character mode, and a decoder that turns what they
+//! send into game events.
+
+use crate::GameEvent;
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const NAWS: u8 = 31;
+
+/// Sent right after connecting. Offering to echo and to suppress go-aheads
+/// makes clients send every key as it's pressed without echoing it, and
+/// asking for NAWS gets us their window size, now and whenever it changes.
+pub(crate) const NEGOTIATION: &[u8] = &[IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD, IAC, DO, NAWS];
+
+/// Longest incomplete command or escape sequence we wait for the rest of.
+/// Anything longer is garbage, and a client could otherwise make us buffer
+/// without end.
+const MAX_PENDING: usize = 64;
+
+/// Turns a client's bytes into game events. Commands and escape sequences may
+/// be split across reads, so whatever is incomplete is kept for the next one.
+#[derive(Default)]
+pub(crate) struct Decoder {
+    pending: Vec<u8>
+}
+
+impl Decoder {
+    pub(crate) fn feed(&mut self, bytes: &[u8], out: &mut Vec<GameEvent>) {
+        self.pending.extend_from_slice(bytes);
+        let mut start = 0;
+        while let Some((length, event)) = decode(&self.pending[start..]) {
+            out.extend(event);
+            start += length;
+        }
+        self.pending.drain(..start);
+        if self.pending.len() > MAX_PENDING {
+            self.pending.clear();
+        }
+    }
+}
+
+/// Decodes the command or key at the start of `bytes`, returning how many
+/// bytes it took and the event it stands for, or `None` if it's incomplete.
+fn decode(bytes: &[u8]) -> Option<(usize, Option<GameEvent>)> {
+    match bytes {
+        [] | [IAC] | [IAC, WILL | WONT | DO | DONT] => None,
+        [IAC, SB, data @ ..] => {
+            let (length, data) = subnegotiation(data)?;
+            Some((2 + length, window_size(&data)))
+        }
+        // answers to our offers, and whatever the client asks for: we only
+        // support what we offered, so there's nothing to answer
+        [IAC, WILL | WONT | DO | DONT, _, ..] => Some((3, None)),
+        // an escaped 255 is data, but not a key we know; everything else is a
+        // command without arguments, like NOP or go-ahead
+        [IAC, _, ..] => Some((2, None)),
+        [0x1b, b'[' | b'O'] => None,
+        [0x1b, b'[' | b'O', key, ..] => Some((3, arrow(*key))),
+        // an escape with nothing that makes it a sequence is the key itself
+        [0x1b, ..] => Some((1, Some(GameEvent::Escape))),
+        [byte, ..] => Some((1, key(*byte)))
+    }
+}
+
+/// Finds the end of a subnegotiation that started right before `bytes`,
+/// returning the length up to and including `IAC SE` and the data with
+/// escaped 255s unescaped.
+fn subnegotiation(bytes: &[u8]) -> Option<(usize, Vec<u8>)> {
+    let mut data = Vec::new();
+    let mut index = 0;
+    loop {
+        match bytes.get(index..)? {
+            [IAC, SE, ..] => return Some((index + 2, data)),
+            [IAC, IAC, ..] => {
+                data.push(IAC);
+                index += 2;
+            }
+            [IAC] | [] => return None,
+            [byte, ..] => {
+                data.push(*byte);
+                index += 1;
+            }
+        }
+    }
+}
+
+fn window_size(data: &[u8]) -> Option<GameEvent> {
+    match *data {
+        [NAWS, w1, w0, h1, h0] => Some(GameEvent::Resize(u16::from_be_bytes([w1, w0]), u16::from_be_bytes([h1, h0]))),
+        _ => None
+    }
+}
+
+fn arrow(key: u8) -> Option<GameEvent> {
+    match key {
+        b'A' => Some(GameEvent::Up),
+        b'B' => Some(GameEvent::Down),
+        b'C' => Some(GameEvent::Right),
+        b'D' => Some(GameEvent::Left),
+        _ => None
+    }
+}
+
+/// The same keys as on the terminal, see `translate_event`. Ctrl-C and
+/// Ctrl-D quit as well, since there's no shell to send a signal to.
+fn key(byte: u8) -> Option<GameEvent> {
+    match byte {
+        b'w' => Some(GameEvent::Up),
+        b'd' => Some(GameEvent::Right),
+        b's' => Some(GameEvent::Down),
+        b'a' => Some(GameEvent::Left),
+        b' ' => Some(GameEvent::Action),
+        b'q' | 0x03 | 0x04 => Some(GameEvent::Quit),
+        0x0c => Some(GameEvent::Redraw),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(chunks: &[&[u8]]) -> Vec<String> {
+        let mut decoder = Decoder::default();
+        let mut out = Vec::new();
+        for chunk in chunks {
+            decoder.feed(chunk, &mut out);
+        }
+        out.iter().map(|event| match event {
+            GameEvent::Resize(width, height) => format!("{width}x{height}"),
+            GameEvent::Up => "up".to_string(),
+            GameEvent::Right => "right".to_string(),
+            GameEvent::Down => "down".to_string(),
+            GameEvent::Left => "left".to_string(),
+            GameEvent::Action => "action".to_string(),
+            GameEvent::Escape => "escape".to_string(),
+            GameEvent::Quit => "quit".to_string(),
+            _ => "other".to_string()
+        }).collect()
+    }
+
+    #[test]
+    fn keys_and_arrows_become_events() {
+        assert_eq!(events(&[b"\x1b[A\x1bOBd a\x1b", b"q"]), ["up", "down", "right", "action", "left", "escape", "quit"]);
+    }
+
+    #[test]
+    fn negotiation_is_skipped() {
+        let answers = [IAC, DO, ECHO, IAC, DO, SUPPRESS_GO_AHEAD, IAC, WILL, NAWS, IAC, 241, b'w'];
+        assert_eq!(events(&[&answers]), ["up"]);
+    }
+
+    #[test]
+    fn window_sizes_are_read_across_reads() {
+        let naws = [IAC, SB, NAWS, 0, 132, 0, 43, IAC, SE];
+        assert_eq!(events(&[&naws[..4], &naws[4..8], &naws[8..]]), ["132x43"]);
+        // a 255 in the size is doubled
+        assert_eq!(events(&[&[IAC, SB, NAWS, 1, IAC, IAC, 0, 50, IAC, SE]]), ["511x50"]);
+    }
+
+    #[test]
+    fn sequences_split_across_reads_wait_for_the_rest() {
+        assert_eq!(events(&[b"\x1b[", b"C", &[IAC], &[WILL], &[ECHO], b"s"]), ["right", "down"]);
+    }
+
+    #[test]
+    fn endless_subnegotiations_are_dropped() {
+        let mut decoder = Decoder::default();
+        let mut out = Vec::new();
+        decoder.feed(&[IAC, SB, NAWS], &mut out);
+        decoder.feed(&[0; 100], &mut out);
+        assert!(decoder.pending.is_empty());
+        decoder.feed(b"a", &mut out);
+        assert!(matches!(out[..], [GameEvent::Left]));
+    }
+}
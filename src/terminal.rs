@@ -23,6 +23,7 @@ impl Default for Cell {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct Terminal {
     foreground: TermColor,
     background: TermColor,
@@ -48,6 +49,38 @@ impl Terminal {
         self.is_related = false;
     }
 
+    /// Turns this terminal into a copy of `other`, reusing the rows that are
+    /// already allocated.
+    pub fn copy_from(&mut self, other: &Terminal) {
+        self.foreground = other.foreground;
+        self.background = other.background;
+        self.size = other.size;
+        self.cursor = other.cursor;
+        self.is_related = other.is_related;
+        self.rows.clone_from(&other.rows);
+    }
+
+    /// Blanks every cell and moves the cursor back home.
+    pub fn reset(&mut self) {
+        self.foreground = TermColor::Default;
+        self.background = TermColor::Default;
+        self.cursor = (0, 0);
+        self.is_related = true;
+        for row in self.rows.iter_mut() {
+            row.fill(Cell::default());
+        }
+    }
+
+    /// Copies `other` onto this terminal with its top left corner at `x`,
+    /// `y`, cutting off what doesn't fit.
+    pub fn paste(&mut self, other: &Terminal, x: usize, y: usize) {
+        for (row, source) in self.rows.iter_mut().skip(y).zip(&other.rows) {
+            for (cell, source) in row.iter_mut().skip(x).zip(source) {
+                *cell = *source;
+            }
+        }
+    }
+
     pub fn set_foreground(&mut self, color: TermColor) {
         self.foreground = color;
     }
@@ -62,10 +95,6 @@ impl Terminal {
         self.cursor = (x, y);
     }
 
-    pub fn up(&mut self, amount: usize) {
-        self.goto(self.cursor.0, self.cursor.1.checked_sub(amount).unwrap_or(0));
-    }
-
     pub fn right(&mut self, amount: usize) {
         self.goto(self.cursor.0 + amount, self.cursor.1);
     }
@@ -75,7 +104,7 @@ impl Terminal {
     }
 
     pub fn left(&mut self, amount: usize) {
-        self.goto(self.cursor.0.checked_sub(amount).unwrap_or(0), self.cursor.1);
+        self.goto(self.cursor.0.saturating_sub(amount), self.cursor.1);
     }
 
     pub fn ret(&mut self) {
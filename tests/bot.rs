@@ -0,0 +1,57 @@
+//! Runs the binary headlessly against bots speaking the line-based protocol.
+
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::thread;
+
+fn nibbles(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_nibbles")).args(args).output().unwrap();
+    assert!(output.status.success(), "nibbles failed: {}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn example_bot() -> PathBuf {
+    // examples are built next to the binary, unless the tests were narrowed
+    // down to a single target
+    let bin = PathBuf::from(env!("CARGO_BIN_EXE_nibbles"));
+    let bot = bin.parent().unwrap().join("examples").join("bot");
+    assert!(bot.exists(), "{} is missing, build it with `cargo build --example bot`", bot.display());
+    bot
+}
+
+#[test]
+fn example_bot_plays_whole_games() {
+    let bot = example_bot();
+    let output = nibbles(&["--bot", bot.to_str().unwrap(), "--bot-games", "2", "--bot-deadline", "1000"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("game 2: seed"), "no second game in output:\n{stdout}");
+    assert!(stdout.contains("mean score"), "no summary in output:\n{stdout}");
+    assert!(stdout.contains(", 0 late replies"), "the bot missed a deadline:\n{stdout}");
+}
+
+#[test]
+fn silent_bots_run_out_of_time_and_go_straight() {
+    let path = std::env::temp_dir().join(format!("nibbles-bot-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let bot = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut states = 0;
+        for line in BufReader::new(stream).lines() {
+            let state: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+            assert!(state["walls"].is_array() && state["snake"].is_array());
+            states += 1;
+        }
+        states
+    });
+
+    let output = nibbles(&["--bot-socket", path.to_str().unwrap(), "--bot-deadline", "5"]);
+    let states = bot.join().unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("{states} ticks, {states} late replies")), "{states} states sent:\n{stdout}");
+}
+
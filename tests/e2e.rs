@@ -0,0 +1,243 @@
+//! End-to-end tests driving the real binary through a pseudo-terminal.
+
+mod support;
+
+use std::time::Duration;
+
+use support::{Game, Viewer};
+
+const CONTINUE_MSG: &str = "Press SPACE to continue";
+
+fn border_intact(screen: &support::vt::Screen) -> bool {
+    let (width, height) = (screen.width, screen.height);
+    (1..height).all(|y| screen.cell(0, y).ch == '\u{2588}' && screen.cell(width - 1, y).ch == '\u{2588}')
+}
+
+#[test]
+fn starts_paused_on_the_alternate_screen_and_quits_cleanly() {
+    let mut game = Game::spawn(80, 24);
+    game.wait_for_text(CONTINUE_MSG);
+    game.screen(|screen| {
+        assert!(screen.alternate, "game should run on the alternate screen");
+        assert!(!screen.cursor_visible, "cursor should be hidden while playing");
+        assert!(screen.row(0).contains("Paused"));
+        assert!(border_intact(screen));
+    });
+    assert!(!game.is_cooked(), "terminal should be in raw mode while playing");
+
+    game.send(b"q");
+    assert!(game.wait_for_exit().success());
+    game.screen(|screen| {
+        assert!(!screen.alternate, "alternate screen should be left on exit");
+        assert!(screen.cursor_visible, "cursor should be shown again on exit");
+    });
+    assert!(game.is_cooked(), "raw mode should be disabled on exit");
+}
+
+#[test]
+fn space_starts_and_escape_pauses() {
+    let mut game = Game::spawn(80, 24);
+    game.wait_for_text(CONTINUE_MSG);
+    game.send(b" ");
+    game.wait_for_text("Lives: 5");
+    game.wait_for("overlay to disappear", |screen| !screen.text().contains(CONTINUE_MSG));
+    game.send(b"\x1b");
+    game.wait_for_text(CONTINUE_MSG);
+}
+
+#[test]
+fn prompt_pulses_while_paused() {
+    let game = Game::spawn(80, 24);
+    game.wait_for_text(CONTINUE_MSG);
+    // nothing moves while paused, so these frames aren't coming from ticks
+    game.wait_for("prompt to change colour", |screen| {
+        (0..screen.height).any(|y| match screen.row(y).find(CONTINUE_MSG) {
+            Some(x) => screen.cell(x, y).fg != Some(15),
+            None => false
+        })
+    });
+}
+
+#[test]
+fn incremental_frames_match_a_full_repaint() {
+    // the pulsing prompt could change colour between the two screens
+    let mut game = Game::spawn_with_args(80, 24, &["--no-animations"]);
+    game.wait_for_text(CONTINUE_MSG);
+    game.send(b" ");
+    game.wait_for_text("Lives: 5");
+    // let the snake travel a few cells so the diffing has something to do
+    std::thread::sleep(Duration::from_millis(450));
+    game.send(b"\x1b");
+    game.wait_for_text(CONTINUE_MSG);
+    std::thread::sleep(Duration::from_millis(250));
+
+    // the first row holds the frame time, which changes on every tick
+    let incremental: Vec<_> = game.screen(|screen| (1..screen.height).map(|y| screen.grid()[y].clone()).collect());
+    game.screen(|screen| screen.scribble());
+    game.send(b"\x0c");
+    game.wait_for("repaint after ctrl-l", |screen| !screen.text().contains('#'));
+    let repainted: Vec<_> = game.screen(|screen| (1..screen.height).map(|y| screen.grid()[y].clone()).collect());
+    assert!(incremental == repainted, "incrementally drawn screen differs from a full repaint");
+}
+
+#[test]
+fn redraws_after_resize() {
+    let mut game = Game::spawn(80, 24);
+    game.wait_for_text(CONTINUE_MSG);
+    game.resize(100, 30);
+    game.wait_for("border at the new size", |screen| {
+        border_intact(screen) && screen.cell(99, 29).ch == '\u{2588}'
+    });
+    game.resize(60, 20);
+    game.wait_for("border at the smaller size", border_intact);
+    game.send(b"q");
+    assert!(game.wait_for_exit().success());
+}
+
+#[test]
+fn a_terminal_too_small_shows_a_notice_until_it_grows() {
+    let mut game = Game::spawn(20, 8);
+    game.wait_for_text("Terminal too small");
+    game.resize(80, 24);
+    game.wait_for_text(CONTINUE_MSG);
+    game.send(b" ");
+    game.wait_for_text("Lives: 5");
+    game.resize(12, 4);
+    game.wait_for_text("Terminal too");
+    game.resize(80, 24);
+    game.wait_for_text(CONTINUE_MSG);
+    game.wait_for("border at the full size again", border_intact);
+    game.send(b"q");
+    assert!(game.wait_for_exit().success());
+    assert!(!game.data_dir().join("nibbles").join("crashes").exists());
+}
+
+#[test]
+fn frames_are_sent_as_synchronized_updates() {
+    let mut game = Game::spawn(80, 24);
+    game.wait_for_text(CONTINUE_MSG);
+    game.send(b"q");
+    assert!(game.wait_for_exit().success());
+    let output = String::from_utf8_lossy(&game.output()).into_owned();
+    let begins = output.matches("\x1b[?2026h").count();
+    assert!(begins > 0, "no synchronized updates in output");
+    assert_eq!(begins, output.matches("\x1b[?2026l").count());
+
+    let game = Game::spawn_with_args(80, 24, &["--no-sync"]);
+    game.wait_for_text(CONTINUE_MSG);
+    assert!(!String::from_utf8_lossy(&game.output()).contains("\x1b[?2026h"));
+}
+
+#[test]
+fn bandwidth_mode_paints_everything_and_reports_throughput() {
+    let mut game = Game::spawn_with_args(80, 24, &["--bandwidth", "4000"]);
+    game.wait_for_text(CONTINUE_MSG);
+    game.wait_for("complete border", border_intact);
+    game.screen(|screen| assert!(screen.row(0).starts_with("    Paused            ")));
+    game.send(b"q");
+    assert!(game.wait_for_exit().success());
+    let output = String::from_utf8_lossy(&game.output()).into_owned();
+    assert!(output.contains("KiB/s"), "no throughput report in output:\n{output}");
+}
+
+#[test]
+fn autoplay_starts_without_a_key() {
+    let mut game = Game::spawn_with_args(80, 24, &["--autoplay"]);
+    game.wait_for_text("Lives: 5");
+    game.wait_for("overlay to disappear", |screen| !screen.text().contains(CONTINUE_MSG));
+    game.send(b"q");
+    assert!(game.wait_for_exit().success());
+}
+
+#[test]
+fn spectators_see_the_whole_screen_and_follow_along() {
+    let path = std::env::temp_dir().join(format!("nibbles-e2e-spectate-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut game = Game::spawn_with_args(80, 24, &["--spectate-socket", path.to_str().unwrap()]);
+    game.wait_for_text(CONTINUE_MSG);
+
+    // joining after the first frame still shows all of it
+    let viewer = Viewer::connect(&path, 80, 24);
+    viewer.wait_for("the paused game", |screen| screen.text().contains(CONTINUE_MSG) && border_intact(screen));
+    viewer.screen(|screen| assert!(!screen.cursor_visible));
+
+    game.send(b" ");
+    viewer.wait_for("the game to start", |screen| screen.row(0).contains("Lives: 5"));
+
+    game.send(b"q");
+    assert!(game.wait_for_exit().success());
+    viewer.wait_for_close();
+    viewer.screen(|screen| assert!(screen.cursor_visible, "the viewer's cursor should be shown again"));
+    assert!(!path.exists(), "the socket should be removed on exit");
+}
+
+#[test]
+fn spectator_ports_stay_on_this_machine_unless_made_public() {
+    for (args, address) in [(&["--spectate-port", "0"][..], "127.0.0.1:"), (&["--spectate-port", "0", "--spectate-public"], "0.0.0.0:")] {
+        let mut game = Game::spawn_with_args(80, 24, args);
+        game.wait_for_text(CONTINUE_MSG);
+        game.send(b"q");
+        assert!(game.wait_for_exit().success());
+        let output = String::from_utf8_lossy(&game.output()).into_owned();
+        assert!(output.contains(&format!("spectators can watch on {address}")), "{output}");
+    }
+}
+
+#[test]
+fn control_socket_drives_the_game_and_reports_mistakes() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let path = std::env::temp_dir().join(format!("nibbles-e2e-control-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut game = Game::spawn_with_args(80, 24, &["--control-socket", path.to_str().unwrap()]);
+    game.wait_for_text(CONTINUE_MSG);
+
+    let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+    stream.set_read_timeout(Some(support::TIMEOUT)).unwrap();
+    let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+    let mut request = |command: &str| -> serde_json::Value {
+        writeln!(stream, "{command}").unwrap();
+        serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap()
+    };
+
+    let reply = request(r#"{"command":"query-state"}"#);
+    assert_eq!(reply["state"]["paused"], true, "{reply}");
+    assert_eq!(request(r#"{"command":"resume"}"#)["ok"], true);
+    game.wait_for_text("Lives: 5");
+    assert_eq!(request(r#"{"command":"pause"}"#)["ok"], true);
+    game.wait_for_text(CONTINUE_MSG);
+
+    // heading for the top border costs a life, whatever is eaten on the way
+    request(r#"{"command":"resume"}"#);
+    request(r#"{"command":"inject-event","event":"Up"}"#);
+    let mistake = lines.find_map(|line| {
+        let notice: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+        (notice["event"] == "life_lost").then_some(notice)
+    });
+    assert_eq!(mistake.unwrap()["lives"], 4);
+    game.wait_for_text(CONTINUE_MSG);
+
+    game.send(b"q");
+    assert!(game.wait_for_exit().success());
+    assert!(!path.exists(), "the socket should be removed on exit");
+}
+
+#[test]
+fn generated_levels_can_be_played() {
+    let dir = std::env::temp_dir().join(format!("nibbles-e2e-levels-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_nibbles"))
+        .args(["--generate", "maze", "--seed", "9", "--density", "0.4"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let path = dir.join(String::from_utf8(output.stdout).unwrap().trim());
+
+    // written without a terminal, so for an 80x24 one
+    let mut game = Game::spawn_with_args(80, 24, &["--level", path.to_str().unwrap()]);
+    game.wait_for_text(CONTINUE_MSG);
+    game.send(b"q");
+    assert!(game.wait_for_exit().success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
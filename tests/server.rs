@@ -0,0 +1,164 @@
+//! Plays on a `--serve` server over loopback, the way telnet clients would.
+
+#[allow(dead_code)]
+#[path = "support/vt.rs"]
+mod vt;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vt::Screen;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const IAC: u8 = 255;
+
+struct Server {
+    child: Child,
+    address: SocketAddr,
+    output: Arc<Mutex<String>>,
+    data_dir: PathBuf
+}
+
+impl Server {
+    fn start() -> Server {
+        let data_dir = std::env::temp_dir().join(format!("nibbles-server-{}", std::process::id()));
+        let mut child = Command::new(env!("CARGO_BIN_EXE_nibbles"))
+            .args(["--serve", "0"])
+            .env("XDG_DATA_HOME", &data_dir)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let first = lines.next().unwrap().unwrap();
+        let port = first.rsplit(':').next().and_then(|port| port.parse::<u16>().ok());
+        let address = SocketAddr::from(([127, 0, 0, 1], port.unwrap_or_else(|| panic!("no port in {first:?}"))));
+        let output = Arc::new(Mutex::new(String::new()));
+        let reader_output = Arc::clone(&output);
+        thread::spawn(move || {
+            for line in lines.map_while(Result::ok) {
+                let mut output = reader_output.lock().unwrap();
+                output.push_str(&line);
+                output.push('\n');
+            }
+        });
+        Server { child, address, output, data_dir }
+    }
+
+    fn wait_for_output(&self, text: &str) {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            if self.output.lock().unwrap().contains(text) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("timed out waiting for {text:?}, output:\n{}", self.output.lock().unwrap());
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    screen: Arc<Mutex<Screen>>,
+    closed: Arc<Mutex<bool>>
+}
+
+impl Client {
+    /// Connects with a window of `width` by `height`, which it tells the
+    /// server like a telnet client that agreed to NAWS.
+    fn connect(address: SocketAddr, width: u8, height: u8) -> Client {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(&[IAC, 250, 31, 0, width, 0, height, IAC, 240]).unwrap();
+
+        let screen = Arc::new(Mutex::new(Screen::new(width as usize, height as usize)));
+        let closed = Arc::new(Mutex::new(false));
+        let (mut reader, reader_screen, reader_closed) = (stream.try_clone().unwrap(), Arc::clone(&screen), Arc::clone(&closed));
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            // the server only sends three-byte option commands
+            let mut command = 0;
+            while let Ok(read @ 1..) = reader.read(&mut buffer) {
+                let mut screen = reader_screen.lock().unwrap();
+                for &byte in &buffer[..read] {
+                    match (command, byte) {
+                        (0, IAC) => command = 2,
+                        (0, _) => screen.feed(&[byte]),
+                        _ => command -= 1
+                    }
+                }
+            }
+            *reader_closed.lock().unwrap() = true;
+        });
+        Client { stream, screen, closed }
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    fn screen<T>(&self, f: impl FnOnce(&Screen) -> T) -> T {
+        f(&self.screen.lock().unwrap())
+    }
+
+    fn wait_for(&self, what: &str, mut predicate: impl FnMut(&Screen) -> bool) {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            if self.screen(&mut predicate) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("timed out waiting for {what}, screen:\n{}", self.screen(|screen| screen.text()));
+    }
+
+    fn wait_for_row(&self, y: usize, text: &str) {
+        self.wait_for(&format!("{text:?} in row {y}"), |screen| screen.row(y).contains(text));
+    }
+}
+
+#[test]
+fn every_connection_plays_its_own_game() {
+    let server = Server::start();
+    let mut first = Client::connect(server.address, 80, 24);
+    let mut second = Client::connect(server.address, 100, 30);
+    for client in [&first, &second] {
+        client.wait_for_row(0, "Paused");
+        client.screen(|screen| {
+            assert!(screen.alternate, "sessions should run on the alternate screen");
+            assert!(!screen.cursor_visible);
+        });
+    }
+    // each board fills its own client's window
+    second.wait_for("the border at column 99", |screen| screen.cell(99, 10).ch == '\u{2588}');
+
+    first.send(b" ");
+    first.wait_for_row(0, "Lives: 5");
+    thread::sleep(Duration::from_millis(300));
+    assert!(second.screen(|screen| screen.row(0).contains("Paused")), "the second game started with the first");
+
+    // hanging up without quitting leaves the other session running
+    first.stream.shutdown(Shutdown::Both).unwrap();
+    server.wait_for_output("left");
+    second.send(b"\x1b[A ");
+    second.wait_for_row(0, "Lives: 5");
+
+    second.send(b"q");
+    second.wait_for("the connection to close", |_| *second.closed.lock().unwrap());
+    second.screen(|screen| {
+        assert!(!screen.alternate, "quitting should leave the alternate screen");
+        assert!(screen.cursor_visible, "quitting should show the cursor again");
+    });
+}
@@ -0,0 +1,218 @@
+//! Runs the `nibbles` binary inside a pseudo-terminal and keeps a `Screen`
+//! up to date with everything it writes.
+
+pub mod vt;
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vt::Screen;
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Game {
+    master: File,
+    child: Child,
+    screen: Arc<Mutex<Screen>>,
+    output: Arc<Mutex<Vec<u8>>>,
+    data_dir: PathBuf
+}
+
+impl Game {
+    pub fn spawn(width: u16, height: u16) -> Game {
+        Game::spawn_with_args(width, height, &[])
+    }
+
+    pub fn spawn_with_args(width: u16, height: u16, args: &[&str]) -> Game {
+        let (master, slave) = open_pty(width, height);
+        let data_dir = std::env::temp_dir().join(format!("nibbles-e2e-{}-{}", std::process::id(), unique()));
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_nibbles"));
+        command
+            .args(args)
+            .env("TERM", "xterm-256color")
+            .env("XDG_DATA_HOME", &data_dir)
+            .stdin(Stdio::from(slave.try_clone().unwrap()))
+            .stdout(Stdio::from(slave.try_clone().unwrap()))
+            .stderr(Stdio::from(slave));
+        unsafe {
+            command.pre_exec(|| {
+                // become a session leader with the pty as controlling terminal,
+                // so crossterm finds it through /dev/tty and we get SIGWINCH
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn().unwrap();
+
+        let screen = Arc::new(Mutex::new(Screen::new(width as usize, height as usize)));
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut reader = master.try_clone().unwrap();
+        let mut answerer = master.try_clone().unwrap();
+        let (reader_screen, reader_output) = (Arc::clone(&screen), Arc::clone(&output));
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            // reading fails with EIO once the child closed its side
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                let answers = {
+                    let mut screen = reader_screen.lock().unwrap();
+                    screen.feed(&buf[..n]);
+                    std::mem::take(&mut screen.answers)
+                };
+                let _ = answerer.write_all(&answers);
+                reader_output.lock().unwrap().extend_from_slice(&buf[..n]);
+            }
+        });
+
+        Game { master, child, screen, output, data_dir }
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) {
+        self.master.write_all(bytes).unwrap();
+        self.master.flush().unwrap();
+    }
+
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.screen.lock().unwrap().resize(width as usize, height as usize);
+        set_size(self.master.as_raw_fd(), width, height);
+    }
+
+    pub fn screen<T>(&self, f: impl FnOnce(&mut Screen) -> T) -> T {
+        f(&mut self.screen.lock().unwrap())
+    }
+
+    /// Everything the game wrote so far, with the escape sequences.
+    pub fn output(&self) -> Vec<u8> {
+        self.output.lock().unwrap().clone()
+    }
+
+    /// Waits until `predicate` holds for the screen, panicking with the
+    /// screen contents if it doesn't within `TIMEOUT`.
+    pub fn wait_for(&self, what: &str, mut predicate: impl FnMut(&Screen) -> bool) {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            if self.screen(|screen| predicate(screen)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("timed out waiting for {what}, screen:\n{}", self.screen(|screen| screen.text()));
+    }
+
+    pub fn wait_for_text(&self, text: &str) {
+        self.wait_for(&format!("{text:?}"), |screen| screen.text().contains(text));
+    }
+
+    pub fn wait_for_exit(&mut self) -> ExitStatus {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                // give the reader thread a moment to drain the last bytes
+                thread::sleep(Duration::from_millis(100));
+                return status;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("game did not exit, screen:\n{}", self.screen(|screen| screen.text()));
+    }
+
+    /// Whether the pty is back in canonical mode with echo, i.e. the game
+    /// left raw mode.
+    pub fn is_cooked(&self) -> bool {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        assert_eq!(unsafe { libc::tcgetattr(self.master.as_raw_fd(), &mut termios) }, 0);
+        termios.c_lflag & libc::ICANON != 0 && termios.c_lflag & libc::ECHO != 0
+    }
+
+    pub fn data_dir(&self) -> &PathBuf {
+        &self.data_dir
+    }
+}
+
+impl Drop for Game {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// Watches a game through its spectator socket, keeping a `Screen` of its
+/// own up to date.
+pub struct Viewer {
+    screen: Arc<Mutex<Screen>>,
+    closed: Arc<Mutex<bool>>
+}
+
+impl Viewer {
+    pub fn connect(path: &Path, width: usize, height: usize) -> Viewer {
+        let mut stream = UnixStream::connect(path).unwrap();
+        let screen = Arc::new(Mutex::new(Screen::new(width, height)));
+        let closed = Arc::new(Mutex::new(false));
+        let (reader_screen, reader_closed) = (Arc::clone(&screen), Arc::clone(&closed));
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n @ 1..) = stream.read(&mut buf) {
+                reader_screen.lock().unwrap().feed(&buf[..n]);
+            }
+            *reader_closed.lock().unwrap() = true;
+        });
+        Viewer { screen, closed }
+    }
+
+    pub fn screen<T>(&self, f: impl FnOnce(&mut Screen) -> T) -> T {
+        f(&mut self.screen.lock().unwrap())
+    }
+
+    pub fn wait_for(&self, what: &str, mut predicate: impl FnMut(&Screen) -> bool) {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            if self.screen(|screen| predicate(screen)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("timed out waiting for {what}, viewer screen:\n{}", self.screen(|screen| screen.text()));
+    }
+
+    pub fn wait_for_close(&self) {
+        self.wait_for("the stream to end", |_| *self.closed.lock().unwrap());
+    }
+}
+
+fn unique() -> usize {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    COUNTER.fetch_add(1, Ordering::SeqCst)
+}
+
+fn open_pty(width: u16, height: u16) -> (File, OwnedFd) {
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(master >= 0, "posix_openpt failed");
+        assert_eq!(libc::grantpt(master), 0);
+        assert_eq!(libc::unlockpt(master), 0);
+        let mut name = [0 as libc::c_char; 128];
+        assert_eq!(libc::ptsname_r(master, name.as_mut_ptr(), name.len()), 0);
+        let slave = libc::open(CStr::from_ptr(name.as_ptr()).as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+        assert!(slave >= 0, "opening the pty slave failed");
+        set_size(master, width, height);
+        (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+    }
+}
+
+fn set_size(fd: i32, width: u16, height: u16) {
+    let size = libc::winsize { ws_row: height, ws_col: width, ws_xpixel: 0, ws_ypixel: 0 };
+    assert_eq!(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) }, 0);
+}
@@ -0,0 +1,293 @@
+//! A tiny VT100/xterm emulator, covering the subset of escape sequences
+//! crossterm emits for us: cursor movement, erasing, 256-colour SGR and the
+//! private modes for the alternate screen, cursor visibility and synchronized
+//! output, which it also reports on, along with its device attributes.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Option<u8>,
+    pub bg: Option<u8>
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { ch: ' ', fg: None, bg: None }
+    }
+}
+
+enum State {
+    Ground,
+    Escape,
+    Csi(String),
+    Osc
+}
+
+pub struct Screen {
+    pub width: usize,
+    pub height: usize,
+    pub cursor: (usize, usize),
+    pub cursor_visible: bool,
+    pub alternate: bool,
+    pub synchronized: bool,
+    /// Answers to queries, for the other end to read.
+    pub answers: Vec<u8>,
+    fg: Option<u8>,
+    bg: Option<u8>,
+    wrap_pending: bool,
+    primary: Vec<Vec<Cell>>,
+    alt: Vec<Vec<Cell>>,
+    state: State,
+    utf8: Vec<u8>
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Screen {
+        Screen {
+            width,
+            height,
+            cursor: (0, 0),
+            cursor_visible: true,
+            alternate: false,
+            synchronized: false,
+            answers: Vec::new(),
+            fg: None,
+            bg: None,
+            wrap_pending: false,
+            primary: vec![vec![Cell::default(); width]; height],
+            alt: vec![vec![Cell::default(); width]; height],
+            state: State::Ground,
+            utf8: Vec::new()
+        }
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        for grid in [&mut self.primary, &mut self.alt] {
+            grid.resize(height, vec![Cell::default(); width]);
+            for row in grid.iter_mut() {
+                row.resize(width, Cell::default());
+            }
+        }
+        self.width = width;
+        self.height = height;
+        self.cursor = (self.cursor.0.min(width - 1), self.cursor.1.min(height - 1));
+    }
+
+    pub fn grid(&self) -> &Vec<Vec<Cell>> {
+        if self.alternate { &self.alt } else { &self.primary }
+    }
+
+    fn grid_mut(&mut self) -> &mut Vec<Vec<Cell>> {
+        if self.alternate { &mut self.alt } else { &mut self.primary }
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Cell {
+        self.grid()[y][x]
+    }
+
+    /// The characters of row `y`, trailing spaces included.
+    pub fn row(&self, y: usize) -> String {
+        self.grid()[y].iter().map(|cell| cell.ch).collect()
+    }
+
+    pub fn text(&self) -> String {
+        (0..self.height).map(|y| self.row(y) + "\n").collect()
+    }
+
+    /// Blanks the visible grid without telling the application, as if
+    /// something else had scribbled over the screen.
+    pub fn scribble(&mut self) {
+        let (width, height) = (self.width, self.height);
+        *self.grid_mut() = vec![vec![Cell { ch: '#', fg: None, bg: None }; width]; height];
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.byte(byte);
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        match std::mem::replace(&mut self.state, State::Ground) {
+            State::Ground => self.ground(byte),
+            State::Escape => match byte {
+                b'[' => self.state = State::Csi(String::new()),
+                b']' => self.state = State::Osc,
+                b'7' | b'8' | b'=' | b'>' => (),
+                _ => ()
+            },
+            State::Csi(mut params) => {
+                if (0x40..=0x7e).contains(&byte) {
+                    self.csi(&params, byte as char);
+                } else {
+                    params.push(byte as char);
+                    self.state = State::Csi(params);
+                }
+            }
+            State::Osc => match byte {
+                0x07 | 0x9c => (),
+                0x1b => self.state = State::Escape,
+                _ => self.state = State::Osc
+            }
+        }
+    }
+
+    fn ground(&mut self, byte: u8) {
+        if !self.utf8.is_empty() || byte >= 0x80 {
+            self.utf8.push(byte);
+            match std::str::from_utf8(&self.utf8) {
+                Ok(s) => {
+                    let ch = s.chars().next().unwrap();
+                    self.utf8.clear();
+                    self.print(ch);
+                }
+                Err(err) if err.error_len().is_some() => {
+                    self.utf8.clear();
+                    self.print('\u{fffd}');
+                }
+                Err(_) => ()
+            }
+            return;
+        }
+        match byte {
+            0x1b => self.state = State::Escape,
+            b'\r' => {
+                self.cursor.0 = 0;
+                self.wrap_pending = false;
+            }
+            b'\n' => self.line_feed(),
+            0x08 => self.cursor.0 = self.cursor.0.saturating_sub(1),
+            0x00..=0x1f | 0x7f => (),
+            _ => self.print(byte as char)
+        }
+    }
+
+    fn line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.1 + 1 < self.height {
+            self.cursor.1 += 1;
+        } else {
+            let width = self.width;
+            let grid = self.grid_mut();
+            grid.remove(0);
+            grid.push(vec![Cell::default(); width]);
+        }
+    }
+
+    fn print(&mut self, ch: char) {
+        if self.wrap_pending {
+            self.cursor.0 = 0;
+            self.line_feed();
+        }
+        let (x, y) = self.cursor;
+        let cell = Cell { ch, fg: self.fg, bg: self.bg };
+        self.grid_mut()[y][x] = cell;
+        if x + 1 < self.width {
+            self.cursor.0 += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn csi(&mut self, params: &str, command: char) {
+        match (params, command) {
+            // DECRQM for synchronized output, which is reset
+            ("?2026$", 'p') => return self.answers.extend_from_slice(b"\x1b[?2026;2$y"),
+            // primary device attributes: a VT220
+            ("" | "0", 'c') => return self.answers.extend_from_slice(b"\x1b[?62;22c"),
+            _ => ()
+        }
+        if let Some(private) = params.strip_prefix('?') {
+            let set = command == 'h';
+            for mode in private.split(';') {
+                match mode {
+                    "25" => self.cursor_visible = set,
+                    "1049" => {
+                        self.alternate = set;
+                        if set {
+                            let (width, height) = (self.width, self.height);
+                            self.alt = vec![vec![Cell::default(); width]; height];
+                        }
+                    }
+                    "2026" => self.synchronized = set,
+                    _ => ()
+                }
+            }
+            return;
+        }
+
+        let numbers: Vec<usize> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        let arg = |index: usize, default: usize| match numbers.get(index) {
+            Some(0) | None => default,
+            Some(&n) => n
+        };
+        self.wrap_pending = false;
+        match command {
+            'A' => self.cursor.1 = self.cursor.1.saturating_sub(arg(0, 1)),
+            'B' => self.cursor.1 = (self.cursor.1 + arg(0, 1)).min(self.height - 1),
+            'C' => self.cursor.0 = (self.cursor.0 + arg(0, 1)).min(self.width - 1),
+            'D' => self.cursor.0 = self.cursor.0.saturating_sub(arg(0, 1)),
+            'G' => self.cursor.0 = (arg(0, 1) - 1).min(self.width - 1),
+            'H' | 'f' => {
+                self.cursor = ((arg(1, 1) - 1).min(self.width - 1), (arg(0, 1) - 1).min(self.height - 1));
+            }
+            'J' => self.erase_display(arg(0, 0)),
+            'K' => self.erase_line(arg(0, 0)),
+            'm' => self.sgr(&numbers),
+            _ => ()
+        }
+    }
+
+    fn erase_display(&mut self, mode: usize) {
+        let (x, y) = self.cursor;
+        let width = self.width;
+        let grid = self.grid_mut();
+        match mode {
+            0 => {
+                grid[y][x..].fill(Cell::default());
+                grid[y + 1..].iter_mut().for_each(|row| row.fill(Cell::default()));
+            }
+            1 => {
+                grid[..y].iter_mut().for_each(|row| row.fill(Cell::default()));
+                grid[y][..=x.min(width - 1)].fill(Cell::default());
+            }
+            _ => grid.iter_mut().for_each(|row| row.fill(Cell::default()))
+        }
+    }
+
+    fn erase_line(&mut self, mode: usize) {
+        let (x, y) = self.cursor;
+        let row = &mut self.grid_mut()[y];
+        match mode {
+            0 => row[x..].fill(Cell::default()),
+            1 => row[..=x].fill(Cell::default()),
+            _ => row.fill(Cell::default())
+        }
+    }
+
+    fn sgr(&mut self, numbers: &[usize]) {
+        let mut iter = numbers.iter().copied();
+        while let Some(n) = iter.next() {
+            match n {
+                0 => {
+                    self.fg = None;
+                    self.bg = None;
+                }
+                38 | 48 => {
+                    let color = match (iter.next(), iter.next()) {
+                        (Some(5), Some(c)) => Some(c as u8),
+                        _ => None
+                    };
+                    if n == 38 { self.fg = color } else { self.bg = color }
+                }
+                30..=37 => self.fg = Some((n - 30) as u8),
+                40..=47 => self.bg = Some((n - 40) as u8),
+                90..=97 => self.fg = Some((n - 90 + 8) as u8),
+                100..=107 => self.bg = Some((n - 100 + 8) as u8),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                _ => ()
+            }
+        }
+    }
+}